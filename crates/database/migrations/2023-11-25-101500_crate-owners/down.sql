@@ -0,0 +1,11 @@
+-- Return to a single owner per crate
+
+ALTER TABLE krate ADD COLUMN owner INTEGER REFERENCES identity(id);
+
+UPDATE krate SET owner = (
+    SELECT MIN(identity) FROM krate_owner WHERE krate_owner.krate = krate.id
+);
+
+ALTER TABLE krate ALTER COLUMN owner SET NOT NULL;
+
+DROP TABLE krate_owner;
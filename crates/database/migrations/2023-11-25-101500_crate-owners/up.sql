@@ -0,0 +1,12 @@
+-- Allow crates to have multiple owners
+
+CREATE TABLE krate_owner (
+    krate INTEGER NOT NULL REFERENCES krate(id),
+    identity INTEGER NOT NULL REFERENCES identity(id),
+
+    PRIMARY KEY (krate, identity)
+);
+
+INSERT INTO krate_owner (krate, identity) SELECT id, owner FROM krate;
+
+ALTER TABLE krate DROP COLUMN owner;
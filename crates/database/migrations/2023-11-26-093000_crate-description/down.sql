@@ -0,0 +1,3 @@
+-- Remove crate descriptions
+
+ALTER TABLE krate DROP COLUMN description;
@@ -0,0 +1,3 @@
+-- Record the description of a crate for search
+
+ALTER TABLE krate ADD COLUMN description VARCHAR;
@@ -0,0 +1,3 @@
+-- Remove normalized crate names
+
+ALTER TABLE krate DROP COLUMN normalized_name;
@@ -0,0 +1,9 @@
+-- Crate names which differ only in case or in '-' vs '_' are the same to cargo
+
+ALTER TABLE krate ADD COLUMN normalized_name VARCHAR;
+
+UPDATE krate SET normalized_name = lower(replace(name, '-', '_'));
+
+ALTER TABLE krate ALTER COLUMN normalized_name SET NOT NULL;
+
+ALTER TABLE krate ADD CONSTRAINT krate_normalized_name_unique UNIQUE (normalized_name);
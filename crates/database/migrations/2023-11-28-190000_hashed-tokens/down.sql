@@ -0,0 +1,10 @@
+-- Return to plaintext tokens.  The original secrets cannot be recovered
+-- from their hashes, so every token is given a fresh secret.
+
+DROP INDEX token_prefix;
+
+ALTER TABLE token ADD COLUMN content VARCHAR NOT NULL UNIQUE DEFAULT md5(gen_random_uuid()::varchar);
+
+ALTER TABLE token DROP COLUMN prefix;
+ALTER TABLE token DROP COLUMN salt;
+ALTER TABLE token DROP COLUMN hash;
@@ -0,0 +1,20 @@
+-- Store only a salted hash of each token, along with a short prefix which
+-- can be displayed to help users identify their tokens
+
+ALTER TABLE token ADD COLUMN prefix VARCHAR;
+ALTER TABLE token ADD COLUMN salt VARCHAR;
+ALTER TABLE token ADD COLUMN hash VARCHAR;
+
+UPDATE token SET
+    prefix = substr(content, 1, 8),
+    salt = md5(gen_random_uuid()::varchar);
+
+UPDATE token SET hash = encode(sha256(convert_to(salt || content, 'UTF8')), 'hex');
+
+ALTER TABLE token ALTER COLUMN prefix SET NOT NULL;
+ALTER TABLE token ALTER COLUMN salt SET NOT NULL;
+ALTER TABLE token ALTER COLUMN hash SET NOT NULL;
+
+ALTER TABLE token DROP COLUMN content;
+
+CREATE INDEX token_prefix ON token(prefix);
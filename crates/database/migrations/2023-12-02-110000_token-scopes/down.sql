@@ -0,0 +1,4 @@
+-- Remove token scopes
+
+ALTER TABLE token DROP COLUMN endpoint_scopes;
+ALTER TABLE token DROP COLUMN crate_scopes;
@@ -0,0 +1,5 @@
+-- Tokens may be restricted to certain endpoints and crates.  A NULL list
+-- means the token is not restricted in that way.
+
+ALTER TABLE token ADD COLUMN endpoint_scopes VARCHAR[];
+ALTER TABLE token ADD COLUMN crate_scopes VARCHAR[];
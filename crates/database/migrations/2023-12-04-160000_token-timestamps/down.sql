@@ -0,0 +1,5 @@
+-- Remove token timestamps
+
+ALTER TABLE token DROP COLUMN created_at;
+ALTER TABLE token DROP COLUMN expires_at;
+ALTER TABLE token DROP COLUMN last_used_at;
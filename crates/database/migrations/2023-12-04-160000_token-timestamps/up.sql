@@ -0,0 +1,5 @@
+-- Track when tokens were created and last used, and allow them to expire
+
+ALTER TABLE token ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE token ADD COLUMN expires_at TIMESTAMPTZ;
+ALTER TABLE token ADD COLUMN last_used_at TIMESTAMPTZ;
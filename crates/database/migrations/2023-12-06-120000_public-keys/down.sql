@@ -0,0 +1,3 @@
+-- Remove public keys
+
+DROP TABLE public_key;
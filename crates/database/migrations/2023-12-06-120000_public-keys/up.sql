@@ -0,0 +1,10 @@
+-- Public keys used to verify asymmetric (PASETO) tokens
+
+CREATE TABLE public_key (
+    id SERIAL PRIMARY KEY,
+    identity INTEGER NOT NULL REFERENCES identity(id),
+    title VARCHAR NOT NULL,
+    key_id VARCHAR NOT NULL UNIQUE,
+    paserk VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
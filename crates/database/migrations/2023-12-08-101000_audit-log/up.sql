@@ -0,0 +1,17 @@
+-- A record of every change made to the registry
+
+CREATE TABLE audit_event (
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    actor INTEGER REFERENCES identity(id),
+    token INTEGER,
+    action VARCHAR NOT NULL,
+    subject INTEGER REFERENCES identity(id),
+    krate VARCHAR,
+    version VARCHAR,
+    detail VARCHAR,
+    source VARCHAR
+);
+
+CREATE INDEX audit_event_created_at ON audit_event (created_at);
+CREATE INDEX audit_event_krate ON audit_event (krate);
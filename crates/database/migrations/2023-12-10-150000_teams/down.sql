@@ -0,0 +1,5 @@
+-- Remove groups
+
+DROP TABLE krate_team_owner;
+DROP TABLE team_member;
+DROP TABLE team;
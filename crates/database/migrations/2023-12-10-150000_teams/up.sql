@@ -0,0 +1,20 @@
+-- Groups of identities, which may own crates as a whole
+
+CREATE TABLE team (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE
+);
+
+CREATE TABLE team_member (
+    team INTEGER NOT NULL REFERENCES team(id) ON DELETE CASCADE,
+    identity INTEGER NOT NULL REFERENCES identity(id),
+
+    PRIMARY KEY (team, identity)
+);
+
+CREATE TABLE krate_team_owner (
+    krate INTEGER NOT NULL REFERENCES krate(id),
+    team INTEGER NOT NULL REFERENCES team(id),
+
+    PRIMARY KEY (krate, team)
+);
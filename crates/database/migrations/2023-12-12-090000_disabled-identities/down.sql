@@ -0,0 +1,3 @@
+-- Identities can no longer be disabled
+
+ALTER TABLE identity DROP COLUMN disabled;
@@ -0,0 +1,3 @@
+-- Allow identities to be disabled without losing their history
+
+ALTER TABLE identity ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT false;
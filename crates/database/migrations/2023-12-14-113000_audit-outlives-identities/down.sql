@@ -0,0 +1,6 @@
+-- Audit events once again refer to extant identities
+
+UPDATE audit_event SET actor = NULL WHERE actor NOT IN (SELECT id FROM identity);
+UPDATE audit_event SET subject = NULL WHERE subject NOT IN (SELECT id FROM identity);
+ALTER TABLE audit_event ADD CONSTRAINT audit_event_actor_fkey FOREIGN KEY (actor) REFERENCES identity(id);
+ALTER TABLE audit_event ADD CONSTRAINT audit_event_subject_fkey FOREIGN KEY (subject) REFERENCES identity(id);
@@ -0,0 +1,4 @@
+-- Keep the audit log intact when identities are deleted
+
+ALTER TABLE audit_event DROP CONSTRAINT audit_event_actor_fkey;
+ALTER TABLE audit_event DROP CONSTRAINT audit_event_subject_fkey;
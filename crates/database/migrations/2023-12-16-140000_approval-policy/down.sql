@@ -0,0 +1,3 @@
+-- Remove the per-crate approval policy
+
+ALTER TABLE krate DROP COLUMN requires_approval;
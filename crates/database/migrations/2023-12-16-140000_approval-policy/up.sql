@@ -0,0 +1,3 @@
+-- Allow crates to override whether new versions need approval
+
+ALTER TABLE krate ADD COLUMN requires_approval BOOLEAN;
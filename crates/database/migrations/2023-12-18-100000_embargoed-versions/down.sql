@@ -0,0 +1,3 @@
+-- Remove version embargoes
+
+ALTER TABLE kratever DROP COLUMN visible_from;
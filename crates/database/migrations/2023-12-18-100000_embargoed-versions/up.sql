@@ -0,0 +1,3 @@
+-- Allow versions to be uploaded ahead of the time they become visible
+
+ALTER TABLE kratever ADD COLUMN visible_from TIMESTAMPTZ;
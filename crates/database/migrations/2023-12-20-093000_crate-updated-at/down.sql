@@ -0,0 +1,5 @@
+-- Stop tracking when crates change
+
+DROP TRIGGER touch_krate ON kratever;
+DROP FUNCTION nabu_touch_krate();
+ALTER TABLE krate DROP COLUMN updated_at;
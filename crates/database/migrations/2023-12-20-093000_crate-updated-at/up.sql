@@ -0,0 +1,13 @@
+-- Track when each crate's index file last changed, so that it can be cached
+
+ALTER TABLE krate ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+BEGIN
+    UPDATE krate SET updated_at = now() WHERE id = NEW.krate;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER touch_krate AFTER INSERT OR UPDATE ON kratever
+    FOR EACH ROW EXECUTE PROCEDURE nabu_touch_krate();
@@ -0,0 +1,8 @@
+-- Stop announcing crate changes
+
+CREATE OR REPLACE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+BEGIN
+    UPDATE krate SET updated_at = now() WHERE id = NEW.krate;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
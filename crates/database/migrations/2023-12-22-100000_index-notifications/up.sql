@@ -0,0 +1,13 @@
+-- Announce which crate changed whenever a version is written, so that
+-- every server can drop its cached copy of the crate's index file
+
+CREATE OR REPLACE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+DECLARE
+    changed VARCHAR;
+BEGIN
+    UPDATE krate SET updated_at = now() WHERE id = NEW.krate
+        RETURNING normalized_name INTO changed;
+    PERFORM pg_notify('nabu_index', changed);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,3 @@
+-- Remove semver ordering
+
+DROP FUNCTION nabu_semver_key(VARCHAR);
@@ -0,0 +1,30 @@
+-- Order versions by semver precedence, so that the highest version of a
+-- crate can be found by the database rather than by loading every version
+--
+-- The key is an array compared element by element: each numeric part of the
+-- version, then a marker which sorts releases above pre-releases, then each
+-- pre-release identifier with numeric ones below alphanumeric ones.  Build
+-- metadata is ignored.  Elements are bytes so that no collation applies.
+
+CREATE FUNCTION nabu_semver_key(ver VARCHAR) RETURNS BYTEA[] AS $$
+    SELECT
+        ARRAY(
+            SELECT convert_to(lpad(part, 20, '0'), 'UTF8')
+            FROM unnest(string_to_array(core, '.')) WITH ORDINALITY AS c(part, n)
+            ORDER BY n
+        )
+        || convert_to(CASE WHEN pre IS NULL THEN '1' ELSE '0' END, 'UTF8')
+        || ARRAY(
+            SELECT convert_to(
+                CASE WHEN part ~ '^[0-9]+$' THEN '0' || lpad(part, 20, '0') ELSE '1' || part END,
+                'UTF8'
+            )
+            FROM unnest(string_to_array(pre, '.')) WITH ORDINALITY AS p(part, n)
+            ORDER BY n
+        )
+    FROM (
+        SELECT
+            split_part(split_part(ver, '+', 1), '-', 1) AS core,
+            substring(split_part(ver, '+', 1) FROM '-(.*)$') AS pre
+    ) AS parts
+$$ LANGUAGE SQL IMMUTABLE;
@@ -0,0 +1,12 @@
+-- Announce changes under the normalized crate name again
+
+CREATE OR REPLACE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+DECLARE
+    changed VARCHAR;
+BEGIN
+    UPDATE krate SET updated_at = now() WHERE id = NEW.krate
+        RETURNING normalized_name INTO changed;
+    PERFORM pg_notify('nabu_index', changed);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
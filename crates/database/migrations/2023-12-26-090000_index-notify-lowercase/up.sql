@@ -0,0 +1,13 @@
+-- Index files are found by the lowercased crate name rather than the
+-- normalized one, so announce changes under that
+
+CREATE OR REPLACE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+DECLARE
+    changed VARCHAR;
+BEGIN
+    UPDATE krate SET updated_at = now() WHERE id = NEW.krate
+        RETURNING lower(name) INTO changed;
+    PERFORM pg_notify('nabu_index', changed);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
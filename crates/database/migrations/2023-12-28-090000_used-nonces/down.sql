@@ -0,0 +1,3 @@
+-- Forget used nonces
+
+DROP TABLE used_nonce;
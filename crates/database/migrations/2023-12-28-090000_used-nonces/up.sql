@@ -0,0 +1,10 @@
+-- Nonces of asymmetric tokens which have been used, remembered for as long
+-- as the token could be accepted so that it cannot be replayed, even to
+-- another instance of the server
+
+CREATE TABLE used_nonce (
+    nonce VARCHAR PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX used_nonce_expires_at ON used_nonce (expires_at);
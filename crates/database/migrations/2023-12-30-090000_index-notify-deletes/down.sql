@@ -0,0 +1,16 @@
+-- Only touch crates when a version is written again
+
+DROP TRIGGER touch_krate ON kratever;
+CREATE TRIGGER touch_krate AFTER INSERT OR UPDATE ON kratever
+    FOR EACH ROW EXECUTE PROCEDURE nabu_touch_krate();
+
+CREATE OR REPLACE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+DECLARE
+    changed VARCHAR;
+BEGIN
+    UPDATE krate SET updated_at = now() WHERE id = NEW.krate
+        RETURNING lower(name) INTO changed;
+    PERFORM pg_notify('nabu_index', changed);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,18 @@
+-- Deleting a version changes the crate's index file too, so touch and
+-- announce the crate the old row belonged to
+
+CREATE OR REPLACE FUNCTION nabu_touch_krate() RETURNS trigger AS $$
+DECLARE
+    changed VARCHAR;
+BEGIN
+    UPDATE krate SET updated_at = now()
+        WHERE id = CASE TG_OP WHEN 'DELETE' THEN OLD.krate ELSE NEW.krate END
+        RETURNING lower(name) INTO changed;
+    PERFORM pg_notify('nabu_index', changed);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER touch_krate ON kratever;
+CREATE TRIGGER touch_krate AFTER INSERT OR UPDATE OR DELETE ON kratever
+    FOR EACH ROW EXECUTE PROCEDURE nabu_touch_krate();
@@ -3,9 +3,14 @@ use std::{fmt::Display, time::Duration};
 use bb8::ErrorSink;
 use diesel::{ConnectionError, ConnectionResult};
 use diesel_async::pooled_connection::{AsyncDieselConnectionManager, ManagerConfig, PoolError};
-use futures::{future::BoxFuture, FutureExt};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver},
+    future::BoxFuture,
+    FutureExt, StreamExt,
+};
 use lazy_static::lazy_static;
 use rustls::RootCertStore;
+use tokio_postgres::AsyncMessage;
 use tokio_postgres_rustls::MakeRustlsConnect;
 use tracing::error;
 
@@ -13,8 +18,8 @@ mod schema;
 
 pub mod models;
 
-pub use diesel::result::Error as DieselError;
-pub use diesel_async::AsyncPgConnection;
+pub use diesel::result::{DatabaseErrorKind, Error as DieselError};
+pub use diesel_async::{AsyncConnection, AsyncPgConnection};
 
 pub fn apply_migrations(db_url: &str) -> diesel::migration::Result<()> {
     use diesel::{Connection, PgConnection};
@@ -48,7 +53,7 @@ lazy_static! {
     };
 }
 
-fn establish_connection(url: &str) -> BoxFuture<ConnectionResult<AsyncPgConnection>> {
+fn establish_connection(url: &str) -> BoxFuture<'_, ConnectionResult<AsyncPgConnection>> {
     (async {
         let (client, connection) = tokio_postgres::connect(url, MAKE_TLS_CONNECT.clone())
             .await
@@ -91,6 +96,53 @@ pub async fn create_pool(db_url: &str) -> Result<Pool, PoolError> {
         .await
 }
 
+/// The channel on which the lowercased names of crates whose versions have
+/// changed are announced, see the `index-notifications` migration
+pub const INDEX_CHANNEL: &str = "nabu_index";
+
+/// Notifications received on a channel being listened to
+pub struct Notifications {
+    // The connection closes once the client is dropped
+    _client: tokio_postgres::Client,
+    messages: UnboundedReceiver<Result<AsyncMessage, tokio_postgres::Error>>,
+}
+
+impl Notifications {
+    /// The payload of the next notification, or `None` if the connection
+    /// has closed
+    pub async fn next(&mut self) -> Option<Result<String, tokio_postgres::Error>> {
+        loop {
+            match self.messages.next().await? {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    return Some(Ok(notification.payload().to_string()))
+                }
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Open a dedicated connection listening on the given channel
+///
+/// Pooled connections cannot be used for this, since they do not deliver
+/// notifications and are handed between requests.
+pub async fn listen(db_url: &str, channel: &str) -> Result<Notifications, tokio_postgres::Error> {
+    let (client, mut connection) =
+        tokio_postgres::connect(db_url, MAKE_TLS_CONNECT.clone()).await?;
+    let (sender, messages) = mpsc::unbounded();
+    tokio::spawn(
+        futures::stream::poll_fn(move |cx| connection.poll_message(cx))
+            .map(Ok)
+            .forward(sender),
+    );
+    client.batch_execute(&format!("LISTEN {channel}")).await?;
+    Ok(Notifications {
+        _client: client,
+        messages,
+    })
+}
+
 pub use axum_link::Connection;
 
 pub mod axum_link {
@@ -99,7 +151,8 @@ pub mod axum_link {
     use async_trait::async_trait;
     use axum::{
         extract::{FromRef, FromRequestParts},
-        http::{request::Parts, StatusCode},
+        http::{header::CONTENT_TYPE, request::Parts, StatusCode},
+        response::{IntoResponse, Response},
     };
     use diesel_async::{pooled_connection::bb8, AsyncPgConnection};
 
@@ -107,24 +160,56 @@ pub mod axum_link {
 
     pub struct Connection(bb8::PooledConnection<'static, AsyncPgConnection>);
 
+    /// Failure to acquire a database connection for a request
+    #[derive(Debug)]
+    pub struct ConnectionRejection(String);
+
+    impl std::fmt::Display for ConnectionRejection {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Unable to acquire database connection: {}", self.0)
+        }
+    }
+
+    impl std::error::Error for ConnectionRejection {}
+
+    impl IntoResponse for ConnectionRejection {
+        fn into_response(self) -> Response {
+            // Shaped as cargo expects registry errors to be
+            let body = serde_json::json!({ "errors": [{ "detail": self.to_string() }] });
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(CONTENT_TYPE, "application/json")],
+                body.to_string(),
+            )
+                .into_response()
+        }
+    }
+
     #[async_trait]
     impl<S> FromRequestParts<S> for Connection
     where
         Pool: FromRef<S>,
         S: Send + Sync,
     {
-        type Rejection = (StatusCode, String);
+        type Rejection = ConnectionRejection;
 
         #[tracing::instrument(name = "acquire_connection", skip_all)]
         async fn from_request_parts(
             _parts: &mut Parts,
             state: &S,
         ) -> Result<Self, Self::Rejection> {
-            let pool = Pool::from_ref(state);
+            Self::acquire(&Pool::from_ref(state)).await
+        }
+    }
+
+    impl Connection {
+        /// Take a connection from the pool, for handlers which may not need
+        /// one and so do not extract it
+        pub async fn acquire(pool: &Pool) -> Result<Self, ConnectionRejection> {
             let conn = pool
                 .get_owned()
                 .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                .map_err(|e| ConnectionRejection(e.to_string()))?;
             Ok(Self(conn))
         }
     }
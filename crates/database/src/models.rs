@@ -1,16 +1,23 @@
 //! Core model functionality for the Nabu database
 //!
 
-use diesel::{ExpressionMethods, Insertable, OptionalExtension, QueryDsl, QueryResult, Queryable};
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use metadata::index::Entry;
-use semver::{Version, VersionReq};
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, Insertable, JoinOnDsl, NullableExpressionMethods,
+    OptionalExtension, PgExpressionMethods, PgTextExpressionMethods, QueryDsl, QueryResult,
+    Queryable,
+};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use metadata::{index::Entry, name::normalize};
+use semver::{BuildMetadata, Version, VersionReq};
+use time::{Duration, OffsetDateTime};
 
 #[derive(Queryable)]
 pub struct Identity {
     pub id: i32,
     pub name: String,
     pub admin: bool,
+    /// Disabled identities may not authenticate, but keep their history
+    pub disabled: bool,
 }
 
 #[derive(Insertable)]
@@ -25,7 +32,18 @@ pub struct Token {
     pub id: i32,
     pub identity: i32,
     pub title: String,
-    pub content: String,
+    /// The first few characters of the secret, for display purposes
+    pub prefix: String,
+    pub salt: String,
+    /// The SHA256 of the salt followed by the secret
+    pub hash: String,
+    /// The endpoints this token may be used for, or `None` for all of them
+    pub endpoint_scopes: Option<Vec<Option<String>>>,
+    /// Patterns for the crates this token may act upon, or `None` for all crates
+    pub crate_scopes: Option<Vec<Option<String>>>,
+    pub created_at: OffsetDateTime,
+    pub expires_at: Option<OffsetDateTime>,
+    pub last_used_at: Option<OffsetDateTime>,
 }
 
 #[derive(Insertable)]
@@ -33,6 +51,102 @@ pub struct Token {
 pub struct NewToken<'a> {
     pub identity: i32,
     pub title: &'a str,
+    pub prefix: &'a str,
+    pub salt: &'a str,
+    pub hash: &'a str,
+    pub endpoint_scopes: Option<Vec<&'a str>>,
+    pub crate_scopes: Option<Vec<&'a str>>,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+/// The actions a token may be restricted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    PublishNew,
+    PublishUpdate,
+    Yank,
+    ChangeOwners,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PublishNew => "publish-new",
+            Self::PublishUpdate => "publish-update",
+            Self::Yank => "yank",
+            Self::ChangeOwners => "change-owners",
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "publish-new" => Ok(Self::PublishNew),
+            "publish-update" => Ok(Self::PublishUpdate),
+            "yank" => Ok(Self::Yank),
+            "change-owners" => Ok(Self::ChangeOwners),
+            _ => Err(format!(
+                "Unknown scope {s}, expected one of publish-new, publish-update, yank or change-owners"
+            )),
+        }
+    }
+}
+
+/// Restrictions placed on what a token may do, `None` means unrestricted
+#[derive(Debug, Default)]
+pub struct TokenScopes {
+    pub endpoints: Option<Vec<Scope>>,
+    /// Crate names, optionally ending in `*` to match any crate with that prefix
+    pub crates: Option<Vec<String>>,
+}
+
+/// How many characters of a token are kept for display
+const TOKEN_PREFIX_LEN: usize = 8;
+
+/// How stale a token's last use time may become before it is updated,
+/// this avoids writing to the database on every request
+const LAST_USED_GRANULARITY: Duration = Duration::minutes(5);
+
+fn random_hex(bytes: usize) -> String {
+    use rand::RngCore;
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hash_token(salt: &str, secret: &str) -> String {
+    sha256::digest(format!("{salt}{secret}"))
+}
+
+/// A public key registered by an identity, used to verify asymmetric tokens
+#[derive(Queryable)]
+pub struct PublicKey {
+    pub id: i32,
+    pub identity: i32,
+    pub title: String,
+    /// The PASERK ID of the key (`k3.pid...`)
+    pub key_id: String,
+    /// The key itself, in PASERK form (`k3.public...`)
+    pub paserk: String,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::public_key)]
+pub struct NewPublicKey<'a> {
+    pub identity: i32,
+    pub title: &'a str,
+    pub key_id: &'a str,
+    pub paserk: &'a str,
 }
 
 impl Identity {
@@ -61,6 +175,102 @@ impl Identity {
             .await
     }
 
+    pub async fn rename(&mut self, db: &mut AsyncPgConnection, name: &str) -> QueryResult<()> {
+        use crate::schema::identity::dsl;
+        diesel::update(dsl::identity)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::name.eq(name))
+            .execute(db)
+            .await?;
+        self.name = name.to_string();
+        Ok(())
+    }
+
+    pub async fn set_admin(&mut self, db: &mut AsyncPgConnection, admin: bool) -> QueryResult<()> {
+        use crate::schema::identity::dsl;
+        diesel::update(dsl::identity)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::admin.eq(admin))
+            .execute(db)
+            .await?;
+        self.admin = admin;
+        Ok(())
+    }
+
+    /// Delete this identity along with its tokens, keys, crate ownerships
+    /// and team memberships.  Its entries in the audit log are kept.
+    pub async fn delete(&self, db: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::{identity, krate_owner, public_key, team_member, token};
+        let id = self.id;
+        db.transaction(|db| {
+            Box::pin(async move {
+                diesel::delete(token::table.filter(token::identity.eq(id)))
+                    .execute(db)
+                    .await?;
+                diesel::delete(public_key::table.filter(public_key::identity.eq(id)))
+                    .execute(db)
+                    .await?;
+                diesel::delete(krate_owner::table.filter(krate_owner::identity.eq(id)))
+                    .execute(db)
+                    .await?;
+                diesel::delete(team_member::table.filter(team_member::identity.eq(id)))
+                    .execute(db)
+                    .await?;
+                diesel::delete(identity::table.filter(identity::id.eq(id)))
+                    .execute(db)
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// The crates this identity owns directly, not through a team
+    pub async fn krates(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Krate>> {
+        use crate::schema::{krate, krate_owner};
+        krate::table
+            .inner_join(krate_owner::table)
+            .filter(krate_owner::identity.eq(self.id))
+            .select(krate::all_columns)
+            .order_by(krate::name.asc())
+            .get_results(db)
+            .await
+    }
+
+    /// The crates owned by the teams this identity is a member of
+    pub async fn team_krates(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Krate>> {
+        use crate::schema::{krate, krate_team_owner, team_member};
+        krate::table
+            .filter(
+                krate::id.eq_any(
+                    krate_team_owner::table
+                        .inner_join(
+                            team_member::table.on(team_member::team.eq(krate_team_owner::team)),
+                        )
+                        .filter(team_member::identity.eq(self.id))
+                        .select(krate_team_owner::krate),
+                ),
+            )
+            .order_by(krate::name.asc())
+            .get_results(db)
+            .await
+    }
+
+    pub async fn set_disabled(
+        &mut self,
+        db: &mut AsyncPgConnection,
+        disabled: bool,
+    ) -> QueryResult<()> {
+        use crate::schema::identity::dsl;
+        diesel::update(dsl::identity)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::disabled.eq(disabled))
+            .execute(db)
+            .await?;
+        self.disabled = disabled;
+        Ok(())
+    }
+
     pub async fn tokens(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Token>> {
         use crate::schema::token::dsl;
         dsl::token
@@ -69,36 +279,246 @@ impl Identity {
             .await
     }
 
-    pub async fn new_token(&self, db: &mut AsyncPgConnection, title: &str) -> QueryResult<Token> {
+    /// Create a new token for this identity.
+    ///
+    /// The secret is returned alongside the token, this is the only time
+    /// it is available since only its hash is stored.
+    pub async fn new_token(
+        &self,
+        db: &mut AsyncPgConnection,
+        title: &str,
+        scopes: &TokenScopes,
+        expires_at: Option<OffsetDateTime>,
+    ) -> QueryResult<(Token, String)> {
+        let secret = random_hex(16);
+        let salt = random_hex(16);
+        let hash = hash_token(&salt, &secret);
         let newtoken = NewToken {
             identity: self.id,
             title,
+            prefix: &secret[..TOKEN_PREFIX_LEN],
+            salt: &salt,
+            hash: &hash,
+            endpoint_scopes: scopes
+                .endpoints
+                .as_ref()
+                .map(|scopes| scopes.iter().map(Scope::as_str).collect()),
+            crate_scopes: scopes
+                .crates
+                .as_ref()
+                .map(|crates| crates.iter().map(String::as_str).collect()),
+            expires_at,
         };
         use crate::schema::token::dsl;
-        diesel::insert_into(dsl::token)
+        let token = diesel::insert_into(dsl::token)
             .values(&newtoken)
             .get_result(db)
+            .await?;
+        Ok((token, secret))
+    }
+
+    pub async fn public_keys(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<PublicKey>> {
+        use crate::schema::public_key::dsl;
+        dsl::public_key
+            .filter(dsl::identity.eq(self.id))
+            .order_by(dsl::id.asc())
+            .get_results(db)
             .await
     }
 
-    pub async fn delete_token(
+    pub async fn add_public_key(
         &self,
         db: &mut AsyncPgConnection,
-        token: &str,
+        title: &str,
+        key_id: &str,
+        paserk: &str,
+    ) -> QueryResult<PublicKey> {
+        let newkey = NewPublicKey {
+            identity: self.id,
+            title,
+            key_id,
+            paserk,
+        };
+        use crate::schema::public_key::dsl;
+        diesel::insert_into(dsl::public_key)
+            .values(&newkey)
+            .get_result(db)
+            .await
+    }
+
+    pub async fn delete_public_key(
+        &self,
+        db: &mut AsyncPgConnection,
+        key_id: &str,
     ) -> QueryResult<usize> {
+        use crate::schema::public_key::dsl;
+        diesel::delete(dsl::public_key)
+            .filter(dsl::identity.eq(self.id))
+            .filter(dsl::key_id.eq(key_id))
+            .execute(db)
+            .await
+    }
+
+    /// Delete one of this identity's tokens, returning it if it existed
+    pub async fn delete_token(
+        &self,
+        db: &mut AsyncPgConnection,
+        id: i32,
+    ) -> QueryResult<Option<Token>> {
         use crate::schema::token::dsl;
         diesel::delete(dsl::token)
-            .filter(dsl::content.eq(token))
-            .execute(db)
+            .filter(dsl::identity.eq(self.id))
+            .filter(dsl::id.eq(id))
+            .get_result(db)
             .await
+            .optional()
     }
 }
 
 impl Token {
+    /// Find the token matching the given secret
     pub async fn from_token(db: &mut AsyncPgConnection, token: &str) -> QueryResult<Option<Self>> {
         use crate::schema::token::dsl;
-        dsl::token
-            .filter(dsl::content.eq(token))
+        let Some(prefix) = token.get(..TOKEN_PREFIX_LEN) else {
+            return Ok(None);
+        };
+        let candidates: Vec<Token> = dsl::token
+            .filter(dsl::prefix.eq(prefix))
+            .get_results(db)
+            .await?;
+        Ok(candidates
+            .into_iter()
+            .find(|candidate| candidate.matches(token)))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= OffsetDateTime::now_utc())
+            .unwrap_or(false)
+    }
+
+    /// Record that this token has just been used, unless that was
+    /// already recorded recently
+    pub async fn touch(&mut self, db: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::token::dsl;
+        let now = OffsetDateTime::now_utc();
+        if self
+            .last_used_at
+            .map(|last_used_at| now - last_used_at < LAST_USED_GRANULARITY)
+            .unwrap_or(false)
+        {
+            return Ok(());
+        }
+        diesel::update(dsl::token)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::last_used_at.eq(now))
+            .execute(db)
+            .await?;
+        self.last_used_at = Some(now);
+        Ok(())
+    }
+
+    /// The endpoint scopes of this token, `None` if unrestricted
+    pub fn endpoint_scopes(&self) -> Option<Vec<&str>> {
+        self.endpoint_scopes
+            .as_ref()
+            .map(|scopes| scopes.iter().flatten().map(String::as_str).collect())
+    }
+
+    /// The crate patterns of this token, `None` if unrestricted
+    pub fn crate_scopes(&self) -> Option<Vec<&str>> {
+        self.crate_scopes
+            .as_ref()
+            .map(|crates| crates.iter().flatten().map(String::as_str).collect())
+    }
+
+    /// Whether this token permits the given action on the named crate
+    pub fn permits(&self, scope: Scope, krate: &str) -> bool {
+        let endpoint_ok = self
+            .endpoint_scopes()
+            .map(|scopes| scopes.contains(&scope.as_str()))
+            .unwrap_or(true);
+        let krate = normalize(krate);
+        let crate_ok = self
+            .crate_scopes()
+            .map(|patterns| {
+                patterns.into_iter().any(|pattern| {
+                    if let Some(prefix) = pattern.strip_suffix('*') {
+                        krate.starts_with(&normalize(prefix))
+                    } else {
+                        krate == normalize(pattern)
+                    }
+                })
+            })
+            .unwrap_or(true);
+        endpoint_ok && crate_ok
+    }
+
+    /// Whether a token with the given restrictions and expiry would be able
+    /// to do nothing that this token cannot, so that it may be issued using
+    /// this token without escalating its privileges
+    pub fn covers(&self, scopes: &TokenScopes, expires_at: Option<OffsetDateTime>) -> bool {
+        let endpoints_ok = match (self.endpoint_scopes(), &scopes.endpoints) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(mine), Some(theirs)) => theirs.iter().all(|s| mine.contains(&s.as_str())),
+        };
+        let crates_ok = match (self.crate_scopes(), &scopes.crates) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(mine), Some(theirs)) => theirs.iter().all(|theirs| {
+                let (their_stem, their_wild) = match theirs.strip_suffix('*') {
+                    Some(stem) => (normalize(stem), true),
+                    None => (normalize(theirs), false),
+                };
+                mine.iter().any(|mine| match mine.strip_suffix('*') {
+                    Some(stem) => their_stem.starts_with(&normalize(stem)),
+                    None => !their_wild && their_stem == normalize(mine),
+                })
+            }),
+        };
+        let expiry_ok = match (self.expires_at, expires_at) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(mine), Some(theirs)) => theirs <= mine,
+        };
+        endpoints_ok && crates_ok && expiry_ok
+    }
+
+    /// Check a secret against this token's hash, in constant time
+    fn matches(&self, secret: &str) -> bool {
+        let hash = hash_token(&self.salt, secret);
+        hash.len() == self.hash.len()
+            && hash
+                .bytes()
+                .zip(self.hash.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    pub async fn owner(&self, db: &mut AsyncPgConnection) -> QueryResult<Identity> {
+        use crate::schema::identity::dsl;
+        dsl::identity
+            .filter(dsl::id.eq(self.identity))
+            .get_result(db)
+            .await
+    }
+}
+
+/// A single-use value from an asymmetric token
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::used_nonce)]
+pub struct UsedNonce<'a> {
+    pub nonce: &'a str,
+    /// When the token carrying the nonce stops being acceptable
+    pub expires_at: OffsetDateTime,
+}
+
+impl PublicKey {
+    pub async fn by_key_id(db: &mut AsyncPgConnection, key_id: &str) -> QueryResult<Option<Self>> {
+        use crate::schema::public_key::dsl;
+        dsl::public_key
+            .filter(dsl::key_id.eq(key_id))
             .get_result(db)
             .await
             .optional()
@@ -113,28 +533,62 @@ impl Token {
     }
 }
 
+impl UsedNonce<'_> {
+    /// Record the use of the nonce, returning false if it has already been
+    /// used.  Nonces which have expired are forgotten first.
+    pub async fn claim(&self, db: &mut AsyncPgConnection) -> QueryResult<bool> {
+        use crate::schema::used_nonce::dsl;
+        diesel::delete(dsl::used_nonce)
+            .filter(dsl::expires_at.le(OffsetDateTime::now_utc()))
+            .execute(db)
+            .await?;
+        diesel::insert_into(dsl::used_nonce)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(db)
+            .await
+            .map(|inserted| inserted == 1)
+    }
+}
+
 #[derive(Debug, Queryable)]
 pub struct Krate {
     pub id: i32,
     pub name: String,
-    pub owner: i32,
+    pub description: Option<String>,
+    pub normalized_name: String,
+    /// Whether new versions must be approved before they are exposed in
+    /// the index, `None` to follow the registry's configuration
+    pub requires_approval: Option<bool>,
+    /// When any of the crate's versions last changed, maintained by the database
+    pub updated_at: OffsetDateTime,
 }
 
 #[derive(Debug, Insertable)]
 #[diesel(table_name=crate::schema::krate)]
 pub struct NewKrate<'a> {
     pub name: &'a str,
-    pub owner: i32,
+    pub normalized_name: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name=crate::schema::krate_owner)]
+pub struct NewKrateOwner {
+    pub krate: i32,
+    pub identity: i32,
 }
 
 #[derive(Debug, Queryable)]
 pub struct KrateVer {
     pub id: i32,
     pub krate: i32,
+    /// Unexposed versions are awaiting approval, and are left out of the index
     pub exposed: bool,
     pub ver: String,
     pub yanked: bool,
     pub metadata: serde_json::Value,
+    /// Embargoed versions are left out of the index until this time
+    pub visible_from: Option<OffsetDateTime>,
 }
 
 #[derive(Debug, Insertable)]
@@ -145,9 +599,54 @@ pub struct NewKrateVer<'a> {
     pub ver: &'a str,
     pub yanked: bool,
     pub metadata: serde_json::Value,
+    pub visible_from: Option<OffsetDateTime>,
+}
+
+diesel::sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+diesel::sql_function! {
+    /// A key which orders versions by semver precedence, see the
+    /// `semver-ordering` migration
+    fn nabu_semver_key(ver: diesel::sql_types::Text) -> diesel::sql_types::Array<diesel::sql_types::Binary>;
+}
+
+/// A crate found by [`Krate::search`]
+#[derive(Debug, Queryable)]
+pub struct SearchHit {
+    pub name: String,
+    pub description: Option<String>,
+    /// The highest visible version, preferring those which are not yanked
+    pub max_version: Option<String>,
+}
+
+type VisibleNow = diesel::dsl::And<
+    diesel::dsl::Eq<crate::schema::kratever::exposed, bool>,
+    diesel::dsl::Or<
+        diesel::dsl::IsNull<crate::schema::kratever::visible_from>,
+        diesel::dsl::LtEq<
+            diesel::dsl::AssumeNotNull<crate::schema::kratever::visible_from>,
+            OffsetDateTime,
+        >,
+    >,
+>;
+
+/// Filter for versions which have been approved and are not under embargo,
+/// anything else is treated as though it does not exist yet
+fn visible_now() -> VisibleNow {
+    use crate::schema::kratever::dsl;
+    dsl::exposed.eq(true).and(
+        dsl::visible_from.is_null().or(dsl::visible_from
+            .assume_not_null()
+            .le(OffsetDateTime::now_utc())),
+    )
 }
 
 impl Krate {
+    pub async fn all(db: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::krate::dsl;
+        dsl::krate.order_by(dsl::name.asc()).get_results(db).await
+    }
+
     pub async fn by_name(db: &mut AsyncPgConnection, name: &str) -> QueryResult<Option<Self>> {
         use crate::schema::krate::dsl;
         dsl::krate
@@ -157,22 +656,114 @@ impl Krate {
             .optional()
     }
 
+    /// Find a crate by name, ignoring differences in case and `-` versus `_`
+    pub async fn by_normalized_name(
+        db: &mut AsyncPgConnection,
+        name: &str,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::krate::dsl;
+        dsl::krate
+            .filter(dsl::normalized_name.eq(normalize(name)))
+            .get_result(db)
+            .await
+            .optional()
+    }
+
+    /// Find a crate by name ignoring case, as cargo lowercases names in
+    /// index and download paths
+    pub async fn by_name_ignoring_case(
+        db: &mut AsyncPgConnection,
+        name: &str,
+    ) -> QueryResult<Option<Self>> {
+        use crate::schema::krate::dsl;
+        dsl::krate
+            .filter(lower(dsl::name).eq(name.to_lowercase()))
+            .get_result(db)
+            .await
+            .optional()
+    }
+
+    /// Find crates whose name or description contains the query text
+    ///
+    /// The most relevant crates come first: an exact name match, then names
+    /// starting with the query, then names containing it, then the rest.
+    /// Returns the requested page of results and the total number of matches.
+    pub async fn search(
+        db: &mut AsyncPgConnection,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> QueryResult<(Vec<SearchHit>, i64)> {
+        use crate::schema::krate::dsl;
+        use crate::schema::kratever;
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let prefix = format!("{escaped}%");
+        let pattern = format!("%{escaped}%");
+        let matching = || {
+            dsl::krate
+                .filter(
+                    dsl::name
+                        .ilike(&pattern)
+                        .or(dsl::description.ilike(&pattern)),
+                )
+                .filter(diesel::dsl::exists(
+                    kratever::table
+                        .filter(kratever::krate.eq(dsl::id))
+                        .filter(visible_now()),
+                ))
+        };
+        let total = matching().count().get_result(db).await?;
+        let max_version = kratever::table
+            .select(kratever::ver)
+            .filter(kratever::krate.eq(dsl::id))
+            .filter(visible_now())
+            .order_by((
+                kratever::yanked.asc(),
+                nabu_semver_key(kratever::ver).desc(),
+            ))
+            .limit(1)
+            .single_value();
+        let hits = matching()
+            .select((dsl::name, dsl::description, max_version))
+            .order_by((
+                dsl::name.ilike(&escaped).desc(),
+                dsl::name.ilike(&prefix).desc(),
+                dsl::name.ilike(&pattern).desc(),
+                dsl::name.asc(),
+            ))
+            .limit(limit)
+            .offset(offset)
+            .get_results(db)
+            .await?;
+        Ok((hits, total))
+    }
+
+    /// Find the named crate, or create it owned by the publisher.
+    ///
+    /// If the crate already exists and the publisher is not permitted
+    /// to publish to it, then this returns `None`
     pub async fn by_name_or_new(
         db: &mut AsyncPgConnection,
         name: &str,
-        owner: &Identity,
-    ) -> QueryResult<Self> {
-        db.build_transaction()
-            .run(|db| {
-                Box::pin(async move {
-                    if let Some(krate) = Krate::by_name(db, name).await? {
-                        Ok(krate)
+        publisher: &Identity,
+    ) -> QueryResult<Option<Self>> {
+        db.transaction(|db| {
+            Box::pin(async move {
+                if let Some(krate) = Krate::by_name(db, name).await? {
+                    if krate.can_publish(db, publisher).await? {
+                        Ok(Some(krate))
                     } else {
-                        Krate::new(db, name, owner).await
+                        Ok(None)
                     }
-                })
+                } else {
+                    Krate::new(db, name, publisher).await.map(Some)
+                }
             })
-            .await
+        })
+        .await
     }
 
     pub async fn new(
@@ -180,14 +771,179 @@ impl Krate {
         name: &str,
         owner: &Identity,
     ) -> QueryResult<Self> {
+        db.transaction(|db| {
+            Box::pin(async move {
+                use crate::schema::krate::dsl;
+                let newkrate = NewKrate {
+                    name,
+                    normalized_name: normalize(name),
+                };
+                let krate: Krate = diesel::insert_into(dsl::krate)
+                    .values(newkrate)
+                    .get_result(db)
+                    .await?;
+                krate.add_owner(db, owner).await?;
+                Ok(krate)
+            })
+        })
+        .await
+    }
+
+    pub async fn owners(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Identity>> {
+        use crate::schema::{identity, krate_owner};
+        identity::table
+            .inner_join(krate_owner::table)
+            .filter(krate_owner::krate.eq(self.id))
+            .select(identity::all_columns)
+            .order_by(identity::name.asc())
+            .get_results(db)
+            .await
+    }
+
+    /// The teams which own this crate
+    pub async fn team_owners(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Team>> {
+        use crate::schema::{krate_team_owner, team};
+        team::table
+            .inner_join(krate_team_owner::table)
+            .filter(krate_team_owner::krate.eq(self.id))
+            .select(team::all_columns)
+            .order_by(team::name.asc())
+            .get_results(db)
+            .await
+    }
+
+    /// Whether the identity owns this crate, either directly or through
+    /// membership of a team which owns it
+    pub async fn owned_by(
+        &self,
+        db: &mut AsyncPgConnection,
+        identity: &Identity,
+    ) -> QueryResult<bool> {
+        use crate::schema::{krate_owner, krate_team_owner, team_member};
+        let direct = diesel::dsl::exists(
+            krate_owner::table
+                .filter(krate_owner::krate.eq(self.id))
+                .filter(krate_owner::identity.eq(identity.id)),
+        );
+        let via_team = diesel::dsl::exists(
+            krate_team_owner::table
+                .inner_join(team_member::table.on(team_member::team.eq(krate_team_owner::team)))
+                .filter(krate_team_owner::krate.eq(self.id))
+                .filter(team_member::identity.eq(identity.id)),
+        );
+        diesel::select(direct.or(via_team)).get_result(db).await
+    }
+
+    /// Whether anyone besides `except` owns this crate, either directly or
+    /// through membership of a team which owns it.  A team with no members
+    /// cannot act for the crate, so does not count.
+    pub async fn has_owner_except(
+        &self,
+        db: &mut AsyncPgConnection,
+        except: Option<&Identity>,
+    ) -> QueryResult<bool> {
+        use crate::schema::{krate_owner, krate_team_owner, team_member};
+        let except = except.map(|identity| identity.id);
+        let direct = diesel::dsl::exists(
+            krate_owner::table
+                .filter(krate_owner::krate.eq(self.id))
+                .filter(krate_owner::identity.nullable().is_distinct_from(except)),
+        );
+        let via_team = diesel::dsl::exists(
+            krate_team_owner::table
+                .inner_join(team_member::table.on(team_member::team.eq(krate_team_owner::team)))
+                .filter(krate_team_owner::krate.eq(self.id))
+                .filter(team_member::identity.nullable().is_distinct_from(except)),
+        );
+        diesel::select(direct.or(via_team)).get_result(db).await
+    }
+
+    /// Whether the given identity may publish new versions of this crate.
+    ///
+    /// Owners may always publish, and admins may override ownership
+    pub async fn can_publish(
+        &self,
+        db: &mut AsyncPgConnection,
+        identity: &Identity,
+    ) -> QueryResult<bool> {
+        if identity.admin {
+            Ok(true)
+        } else {
+            self.owned_by(db, identity).await
+        }
+    }
+
+    pub async fn add_owner(
+        &self,
+        db: &mut AsyncPgConnection,
+        identity: &Identity,
+    ) -> QueryResult<usize> {
+        use crate::schema::krate_owner::dsl;
+        let newowner = NewKrateOwner {
+            krate: self.id,
+            identity: identity.id,
+        };
+        diesel::insert_into(dsl::krate_owner)
+            .values(newowner)
+            .on_conflict_do_nothing()
+            .execute(db)
+            .await
+    }
+
+    /// Lock the crate until the end of the transaction, so that concurrent
+    /// changes to its owners are made one after the other and each sees
+    /// the owners the previous one left
+    pub async fn lock_owners(&self, db: &mut AsyncPgConnection) -> QueryResult<()> {
         use crate::schema::krate::dsl;
-        let newkrate = NewKrate {
-            name,
-            owner: owner.id,
+        dsl::krate
+            .select(dsl::id)
+            .filter(dsl::id.eq(self.id))
+            .for_update()
+            .get_result::<i32>(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_owner(
+        &self,
+        db: &mut AsyncPgConnection,
+        identity: &Identity,
+    ) -> QueryResult<usize> {
+        use crate::schema::krate_owner::dsl;
+        diesel::delete(dsl::krate_owner)
+            .filter(dsl::krate.eq(self.id))
+            .filter(dsl::identity.eq(identity.id))
+            .execute(db)
+            .await
+    }
+
+    pub async fn add_team_owner(
+        &self,
+        db: &mut AsyncPgConnection,
+        team: &Team,
+    ) -> QueryResult<usize> {
+        use crate::schema::krate_team_owner::dsl;
+        let newowner = NewKrateTeamOwner {
+            krate: self.id,
+            team: team.id,
         };
-        diesel::insert_into(dsl::krate)
-            .values(newkrate)
-            .get_result(db)
+        diesel::insert_into(dsl::krate_team_owner)
+            .values(newowner)
+            .on_conflict_do_nothing()
+            .execute(db)
+            .await
+    }
+
+    pub async fn remove_team_owner(
+        &self,
+        db: &mut AsyncPgConnection,
+        team: &Team,
+    ) -> QueryResult<usize> {
+        use crate::schema::krate_team_owner::dsl;
+        diesel::delete(dsl::krate_team_owner)
+            .filter(dsl::krate.eq(self.id))
+            .filter(dsl::team.eq(team.id))
+            .execute(db)
             .await
     }
 
@@ -195,15 +951,18 @@ impl Krate {
         &self,
         db: &mut AsyncPgConnection,
         entry: &Entry,
+        exposed: bool,
+        visible_from: Option<OffsetDateTime>,
     ) -> QueryResult<KrateVer> {
         use crate::schema::kratever::dsl;
         let newver = NewKrateVer {
             krate: self.id,
-            exposed: true,
+            exposed,
             ver: &entry.vers,
             yanked: entry.yanked,
             metadata: serde_json::to_value(entry)
                 .map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?,
+            visible_from,
         };
         diesel::insert_into(dsl::kratever)
             .values(newver)
@@ -211,11 +970,74 @@ impl Krate {
             .await
     }
 
+    pub async fn version(
+        &self,
+        db: &mut AsyncPgConnection,
+        ver: &str,
+    ) -> QueryResult<Option<KrateVer>> {
+        use crate::schema::kratever::dsl;
+        dsl::kratever
+            .filter(dsl::krate.eq(self.id))
+            .filter(dsl::ver.eq(ver))
+            .get_result(db)
+            .await
+            .optional()
+    }
+
+    /// Find an existing version which is the same as the given one once
+    /// any build metadata is ignored, as crates.io does
+    pub async fn version_ignoring_build(
+        &self,
+        db: &mut AsyncPgConnection,
+        ver: &Version,
+    ) -> QueryResult<Option<KrateVer>> {
+        let strip = |ver: &Version| Version {
+            build: BuildMetadata::EMPTY,
+            ..ver.clone()
+        };
+        let wanted = strip(ver);
+        Ok(self.versions(db).await?.into_iter().find(|existing| {
+            Version::parse(&existing.ver)
+                .map(|existing| strip(&existing) == wanted)
+                .unwrap_or(false)
+        }))
+    }
+
+    pub async fn set_description(
+        &mut self,
+        db: &mut AsyncPgConnection,
+        description: Option<&str>,
+    ) -> QueryResult<()> {
+        use crate::schema::krate::dsl;
+        diesel::update(dsl::krate)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::description.eq(description))
+            .execute(db)
+            .await?;
+        self.description = description.map(String::from);
+        Ok(())
+    }
+
+    /// The highest version of this crate, preferring versions which are not yanked
+    pub async fn max_version(&self, db: &mut AsyncPgConnection) -> QueryResult<Option<Version>> {
+        use crate::schema::kratever::dsl;
+        let ver: Option<String> = dsl::kratever
+            .select(dsl::ver)
+            .filter(dsl::krate.eq(self.id))
+            .filter(visible_now())
+            .order_by((dsl::yanked.asc(), nabu_semver_key(dsl::ver).desc()))
+            .first(db)
+            .await
+            .optional()?;
+        Ok(ver.and_then(|ver| Version::parse(&ver).ok()))
+    }
+
     pub async fn satisfies(&self, db: &mut AsyncPgConnection, req: &str) -> QueryResult<bool> {
         use crate::schema::kratever::dsl;
         let versions: Vec<String> = dsl::kratever
             .select(dsl::ver)
             .filter(dsl::krate.eq(self.id))
+            .filter(visible_now())
             .load(db)
             .await?;
         let req = VersionReq::parse(req)
@@ -234,10 +1056,470 @@ impl Krate {
             .get_results(db)
             .await
     }
+
+    /// When the earliest embargo on an approved version will pass, if any
+    /// is still in force
+    pub async fn next_embargo(
+        &self,
+        db: &mut AsyncPgConnection,
+    ) -> QueryResult<Option<OffsetDateTime>> {
+        use crate::schema::kratever::dsl;
+        dsl::kratever
+            .select(diesel::dsl::min(dsl::visible_from))
+            .filter(dsl::krate.eq(self.id))
+            .filter(dsl::exposed.eq(true))
+            .filter(dsl::visible_from.gt(OffsetDateTime::now_utc()))
+            .get_result(db)
+            .await
+    }
+
+    /// When the crate's index file last changed, which includes any embargo
+    /// having passed since the versions were last written
+    pub fn index_modified(&self, visible: &[KrateVer]) -> OffsetDateTime {
+        visible
+            .iter()
+            .filter_map(|version| version.visible_from)
+            .fold(self.updated_at, OffsetDateTime::max)
+    }
+
+    /// The versions which have been approved and are not under embargo, and
+    /// so belong in the index
+    pub async fn visible_versions(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<KrateVer>> {
+        use crate::schema::kratever::dsl;
+        dsl::kratever
+            .filter(dsl::krate.eq(self.id))
+            .filter(visible_now())
+            .order_by(dsl::id.asc())
+            .get_results(db)
+            .await
+    }
+
+    /// Whether new versions need approval, given the registry's default
+    pub fn needs_approval(&self, default: bool) -> bool {
+        self.requires_approval.unwrap_or(default)
+    }
+
+    pub async fn set_requires_approval(
+        &mut self,
+        db: &mut AsyncPgConnection,
+        requires_approval: Option<bool>,
+    ) -> QueryResult<()> {
+        use crate::schema::krate::dsl;
+        diesel::update(dsl::krate)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::requires_approval.eq(requires_approval))
+            .execute(db)
+            .await?;
+        self.requires_approval = requires_approval;
+        Ok(())
+    }
 }
 
 impl KrateVer {
+    /// Every version awaiting approval, with its crate
+    pub async fn pending(db: &mut AsyncPgConnection) -> QueryResult<Vec<(Krate, KrateVer)>> {
+        use crate::schema::{krate, kratever};
+        krate::table
+            .inner_join(kratever::table)
+            .filter(kratever::exposed.eq(false))
+            .order_by((krate::name.asc(), kratever::id.asc()))
+            .get_results(db)
+            .await
+    }
+
+    /// Remove a version, only for undoing a publish which could not complete
+    pub async fn delete(self, db: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::kratever::dsl;
+        diesel::delete(dsl::kratever)
+            .filter(dsl::id.eq(self.id))
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Whether this version has been approved and is not under embargo
+    pub fn is_visible(&self) -> bool {
+        self.exposed
+            && self
+                .visible_from
+                .is_none_or(|when| when <= OffsetDateTime::now_utc())
+    }
+
+    /// Hide this version from the index until the given time, or release
+    /// it immediately if there is none
+    pub async fn set_visible_from(
+        &mut self,
+        db: &mut AsyncPgConnection,
+        visible_from: Option<OffsetDateTime>,
+    ) -> QueryResult<()> {
+        use crate::schema::kratever::dsl;
+        diesel::update(dsl::kratever)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::visible_from.eq(visible_from))
+            .execute(db)
+            .await?;
+        self.visible_from = visible_from;
+        Ok(())
+    }
+
+    /// Approve this version, exposing it in the index
+    pub async fn expose(&mut self, db: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::kratever::dsl;
+        diesel::update(dsl::kratever)
+            .filter(dsl::id.eq(self.id))
+            .set(dsl::exposed.eq(true))
+            .execute(db)
+            .await?;
+        self.exposed = true;
+        Ok(())
+    }
+
+    /// Set the yanked state of this version, updating the index metadata to match
+    pub async fn set_yanked(
+        &mut self,
+        db: &mut AsyncPgConnection,
+        yanked: bool,
+    ) -> QueryResult<()> {
+        use crate::schema::kratever::dsl;
+        let mut metadata = self.metadata.clone();
+        if let Some(obj) = metadata.as_object_mut() {
+            obj.insert("yanked".into(), serde_json::Value::Bool(yanked));
+        }
+        diesel::update(dsl::kratever)
+            .filter(dsl::id.eq(self.id))
+            .set((dsl::yanked.eq(yanked), dsl::metadata.eq(&metadata)))
+            .execute(db)
+            .await?;
+        self.yanked = yanked;
+        self.metadata = metadata;
+        Ok(())
+    }
+
     pub fn index_line(&self) -> String {
         serde_json::to_string(&self.metadata).expect("Unable to re-serialise valid JSON")
     }
 }
+
+/// A named group of identities, which may own crates in the same way as a
+/// single identity, granting all of its members the rights of an owner
+#[derive(Debug, Queryable)]
+pub struct Team {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::team)]
+pub struct NewTeam<'a> {
+    pub name: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::team_member)]
+pub struct NewTeamMember {
+    pub team: i32,
+    pub identity: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::krate_team_owner)]
+pub struct NewKrateTeamOwner {
+    pub krate: i32,
+    pub team: i32,
+}
+
+/// How a team is written wherever users may also appear, e.g. as an owner
+pub const TEAM_PREFIX: &str = "team:";
+
+impl Team {
+    pub async fn all(db: &mut AsyncPgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::team::dsl;
+        dsl::team.order_by(dsl::name.asc()).get_results(db).await
+    }
+
+    pub async fn by_name(db: &mut AsyncPgConnection, name: &str) -> QueryResult<Option<Self>> {
+        use crate::schema::team::dsl;
+        dsl::team
+            .filter(dsl::name.eq(name))
+            .get_result(db)
+            .await
+            .optional()
+    }
+
+    pub async fn new(db: &mut AsyncPgConnection, name: &str) -> QueryResult<Self> {
+        use crate::schema::team::dsl;
+        diesel::insert_into(dsl::team)
+            .values(NewTeam { name })
+            .get_result(db)
+            .await
+    }
+
+    /// Delete this team and its memberships, it must not own any crates
+    pub async fn delete(&self, db: &mut AsyncPgConnection) -> QueryResult<usize> {
+        use crate::schema::team::dsl;
+        diesel::delete(dsl::team)
+            .filter(dsl::id.eq(self.id))
+            .execute(db)
+            .await
+    }
+
+    /// The name of this team as shown alongside users, `team:<name>`
+    pub fn login(&self) -> String {
+        format!("{TEAM_PREFIX}{}", self.name)
+    }
+
+    pub async fn members(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Identity>> {
+        use crate::schema::{identity, team_member};
+        identity::table
+            .inner_join(team_member::table)
+            .filter(team_member::team.eq(self.id))
+            .select(identity::all_columns)
+            .order_by(identity::name.asc())
+            .get_results(db)
+            .await
+    }
+
+    pub async fn add_member(
+        &self,
+        db: &mut AsyncPgConnection,
+        identity: &Identity,
+    ) -> QueryResult<usize> {
+        use crate::schema::team_member::dsl;
+        let newmember = NewTeamMember {
+            team: self.id,
+            identity: identity.id,
+        };
+        diesel::insert_into(dsl::team_member)
+            .values(newmember)
+            .on_conflict_do_nothing()
+            .execute(db)
+            .await
+    }
+
+    pub async fn remove_member(
+        &self,
+        db: &mut AsyncPgConnection,
+        identity: &Identity,
+    ) -> QueryResult<usize> {
+        use crate::schema::team_member::dsl;
+        diesel::delete(dsl::team_member)
+            .filter(dsl::team.eq(self.id))
+            .filter(dsl::identity.eq(identity.id))
+            .execute(db)
+            .await
+    }
+
+    /// The crates this team owns
+    pub async fn krates(&self, db: &mut AsyncPgConnection) -> QueryResult<Vec<Krate>> {
+        use crate::schema::{krate, krate_team_owner};
+        krate::table
+            .inner_join(krate_team_owner::table)
+            .filter(krate_team_owner::team.eq(self.id))
+            .select(krate::all_columns)
+            .order_by(krate::name.asc())
+            .get_results(db)
+            .await
+    }
+}
+
+/// The kinds of change recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Publish,
+    Yank,
+    Unyank,
+    AddOwner,
+    RemoveOwner,
+    Approve,
+    SetApprovalPolicy,
+    Embargo,
+    LiftEmbargo,
+    CreateToken,
+    DeleteToken,
+    CreateUser,
+    DeleteUser,
+    RenameUser,
+    SetAdmin,
+    UnsetAdmin,
+    DisableUser,
+    EnableUser,
+    AddKey,
+    DeleteKey,
+    CreateTeam,
+    DeleteTeam,
+    AddMember,
+    RemoveMember,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Publish => "publish",
+            Self::Yank => "yank",
+            Self::Unyank => "unyank",
+            Self::AddOwner => "add-owner",
+            Self::RemoveOwner => "remove-owner",
+            Self::Approve => "approve",
+            Self::SetApprovalPolicy => "set-approval-policy",
+            Self::Embargo => "embargo",
+            Self::LiftEmbargo => "lift-embargo",
+            Self::CreateToken => "create-token",
+            Self::DeleteToken => "delete-token",
+            Self::CreateUser => "create-user",
+            Self::DeleteUser => "delete-user",
+            Self::RenameUser => "rename-user",
+            Self::SetAdmin => "set-admin",
+            Self::UnsetAdmin => "unset-admin",
+            Self::DisableUser => "disable-user",
+            Self::EnableUser => "enable-user",
+            Self::AddKey => "add-key",
+            Self::DeleteKey => "delete-key",
+            Self::CreateTeam => "create-team",
+            Self::DeleteTeam => "delete-team",
+            Self::AddMember => "add-member",
+            Self::RemoveMember => "remove-member",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "publish" => Ok(Self::Publish),
+            "yank" => Ok(Self::Yank),
+            "unyank" => Ok(Self::Unyank),
+            "add-owner" => Ok(Self::AddOwner),
+            "remove-owner" => Ok(Self::RemoveOwner),
+            "approve" => Ok(Self::Approve),
+            "set-approval-policy" => Ok(Self::SetApprovalPolicy),
+            "embargo" => Ok(Self::Embargo),
+            "lift-embargo" => Ok(Self::LiftEmbargo),
+            "create-token" => Ok(Self::CreateToken),
+            "delete-token" => Ok(Self::DeleteToken),
+            "create-user" => Ok(Self::CreateUser),
+            "delete-user" => Ok(Self::DeleteUser),
+            "rename-user" => Ok(Self::RenameUser),
+            "set-admin" => Ok(Self::SetAdmin),
+            "unset-admin" => Ok(Self::UnsetAdmin),
+            "disable-user" => Ok(Self::DisableUser),
+            "enable-user" => Ok(Self::EnableUser),
+            "add-key" => Ok(Self::AddKey),
+            "delete-key" => Ok(Self::DeleteKey),
+            "create-team" => Ok(Self::CreateTeam),
+            "delete-team" => Ok(Self::DeleteTeam),
+            "add-member" => Ok(Self::AddMember),
+            "remove-member" => Ok(Self::RemoveMember),
+            _ => Err(format!("Unknown audit action {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Queryable)]
+pub struct AuditEvent {
+    pub id: i32,
+    pub created_at: OffsetDateTime,
+    /// Who made the change, `None` if done from the command line
+    pub actor: Option<i32>,
+    /// The token the change was made with, if any
+    pub token: Option<i32>,
+    pub action: String,
+    /// The identity affected by the change, e.g. a new owner or a token's owner
+    pub subject: Option<i32>,
+    pub krate: Option<String>,
+    pub version: Option<String>,
+    pub detail: Option<String>,
+    /// The address the request came from
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::audit_event)]
+pub struct NewAuditEvent<'a> {
+    pub actor: Option<i32>,
+    pub token: Option<i32>,
+    pub action: &'static str,
+    pub subject: Option<i32>,
+    pub krate: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub detail: Option<&'a str>,
+    pub source: Option<&'a str>,
+}
+
+impl<'a> NewAuditEvent<'a> {
+    /// An event with nothing but its action filled in
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            actor: None,
+            token: None,
+            action: action.as_str(),
+            subject: None,
+            krate: None,
+            version: None,
+            detail: None,
+            source: None,
+        }
+    }
+
+    pub async fn record(&self, db: &mut AsyncPgConnection) -> QueryResult<()> {
+        use crate::schema::audit_event::dsl;
+        diesel::insert_into(dsl::audit_event)
+            .values(self)
+            .execute(db)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// Restrictions on which audit events to retrieve
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    /// Events made by, or affecting, this identity
+    pub identity: Option<i32>,
+    pub krate: Option<String>,
+    pub action: Option<AuditAction>,
+    pub since: Option<OffsetDateTime>,
+    pub until: Option<OffsetDateTime>,
+    pub limit: Option<i64>,
+}
+
+impl AuditEvent {
+    /// Events matching the filter, oldest first
+    pub async fn search(
+        db: &mut AsyncPgConnection,
+        filter: &AuditFilter,
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::audit_event::dsl;
+        let mut query = dsl::audit_event.into_boxed();
+        if let Some(identity) = filter.identity {
+            query = query.filter(dsl::actor.eq(identity).or(dsl::subject.eq(identity)));
+        }
+        if let Some(krate) = &filter.krate {
+            query = query.filter(dsl::krate.eq(krate));
+        }
+        if let Some(action) = filter.action {
+            query = query.filter(dsl::action.eq(action.as_str()));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(dsl::created_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(dsl::created_at.lt(until));
+        }
+        // Take the most recent events when limited, but show them in order
+        query = query.order_by((dsl::created_at.desc(), dsl::id.desc()));
+        if let Some(limit) = filter.limit {
+            query = query.limit(limit);
+        }
+        let mut events: Vec<Self> = query.get_results(db).await?;
+        events.reverse();
+        Ok(events)
+    }
+}
@@ -1,10 +1,26 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_event (id) {
+        id -> Int4,
+        created_at -> Timestamptz,
+        actor -> Nullable<Int4>,
+        token -> Nullable<Int4>,
+        action -> Varchar,
+        subject -> Nullable<Int4>,
+        krate -> Nullable<Varchar>,
+        version -> Nullable<Varchar>,
+        detail -> Nullable<Varchar>,
+        source -> Nullable<Varchar>,
+    }
+}
+
 diesel::table! {
     identity (id) {
         id -> Int4,
         name -> Varchar,
         admin -> Bool,
+        disabled -> Bool,
     }
 }
 
@@ -12,7 +28,24 @@ diesel::table! {
     krate (id) {
         id -> Int4,
         name -> Varchar,
-        owner -> Int4,
+        description -> Nullable<Varchar>,
+        normalized_name -> Varchar,
+        requires_approval -> Nullable<Bool>,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    krate_owner (krate, identity) {
+        krate -> Int4,
+        identity -> Int4,
+    }
+}
+
+diesel::table! {
+    krate_team_owner (krate, team) {
+        krate -> Int4,
+        team -> Int4,
     }
 }
 
@@ -24,6 +57,32 @@ diesel::table! {
         ver -> Varchar,
         yanked -> Bool,
         metadata -> Jsonb,
+        visible_from -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    public_key (id) {
+        id -> Int4,
+        identity -> Int4,
+        title -> Varchar,
+        key_id -> Varchar,
+        paserk -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    team (id) {
+        id -> Int4,
+        name -> Varchar,
+    }
+}
+
+diesel::table! {
+    team_member (team, identity) {
+        team -> Int4,
+        identity -> Int4,
     }
 }
 
@@ -32,17 +91,44 @@ diesel::table! {
         id -> Int4,
         identity -> Int4,
         title -> Varchar,
-        content -> Varchar,
+        prefix -> Varchar,
+        salt -> Varchar,
+        hash -> Varchar,
+        endpoint_scopes -> Nullable<Array<Nullable<Varchar>>>,
+        crate_scopes -> Nullable<Array<Nullable<Varchar>>>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    used_nonce (nonce) {
+        nonce -> Varchar,
+        expires_at -> Timestamptz,
     }
 }
 
-diesel::joinable!(krate -> identity (owner));
+diesel::joinable!(krate_owner -> identity (identity));
+diesel::joinable!(krate_owner -> krate (krate));
+diesel::joinable!(krate_team_owner -> krate (krate));
+diesel::joinable!(krate_team_owner -> team (team));
 diesel::joinable!(kratever -> krate (krate));
+diesel::joinable!(public_key -> identity (identity));
+diesel::joinable!(team_member -> identity (identity));
+diesel::joinable!(team_member -> team (team));
 diesel::joinable!(token -> identity (identity));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_event,
     identity,
     krate,
+    krate_owner,
+    krate_team_owner,
     kratever,
+    public_key,
+    team,
+    team_member,
     token,
+    used_nonce,
 );
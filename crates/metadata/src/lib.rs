@@ -1,4 +1,5 @@
 //! Metadata structures etc. for nabu
 
 pub mod index;
+pub mod name;
 pub mod publish;
@@ -0,0 +1,287 @@
+//! Validated crate names
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The longest crate name we will accept, matching crates.io
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Names which cannot be used for crates, either because they clash with
+/// crates shipped with Rust, or because they are reserved filenames on Windows
+const RESERVED_NAMES: &[&str] = &[
+    "alloc",
+    "core",
+    "proc_macro",
+    "proc-macro",
+    "std",
+    "test",
+    "con",
+    "prn",
+    "aux",
+    "nul",
+    "com1",
+    "com2",
+    "com3",
+    "com4",
+    "com5",
+    "com6",
+    "com7",
+    "com8",
+    "com9",
+    "lpt1",
+    "lpt2",
+    "lpt3",
+    "lpt4",
+    "lpt5",
+    "lpt6",
+    "lpt7",
+    "lpt8",
+    "lpt9",
+];
+
+/// Normalize a crate name, cargo treats names which differ only in case,
+/// or in the use of `-` versus `_`, as the same crate
+pub fn normalize(name: &str) -> String {
+    name.to_ascii_lowercase().replace('-', "_")
+}
+
+/// Check a pattern used to restrict tokens to certain crates, which is
+/// either a crate name or a name prefix followed by `*`.  A lone `*`
+/// matches every crate.
+pub fn check_pattern(pattern: &str) -> Result<(), CrateNameError> {
+    match pattern.strip_suffix('*') {
+        Some("") => Ok(()),
+        Some(prefix) => CrateName::new(prefix).map(|_| ()),
+        None => CrateName::new(pattern).map(|_| ()),
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CrateNameError {
+    #[error("Crate names may not be empty")]
+    Empty,
+    #[error("Crate name {0} is longer than {MAX_NAME_LENGTH} characters")]
+    TooLong(String),
+    #[error("Crate name {0} must start with an ASCII letter")]
+    BadStart(String),
+    #[error("Crate name {name} contains invalid character {ch:?}, only ASCII alphanumerics, '-' and '_' are allowed")]
+    BadChar { name: String, ch: char },
+    #[error("Crate name {0} is reserved")]
+    Reserved(String),
+}
+
+/// A crate name which follows the rules crates.io applies.
+///
+/// Since names are restricted to ASCII, byte based indexing into them is safe
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CrateName(String);
+
+impl CrateName {
+    /// Validate a crate name
+    pub fn new(name: &str) -> Result<Self, CrateNameError> {
+        let first = name.chars().next().ok_or(CrateNameError::Empty)?;
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(CrateNameError::TooLong(name.to_string()));
+        }
+        if !first.is_ascii_alphabetic() {
+            return Err(CrateNameError::BadStart(name.to_string()));
+        }
+        if let Some(ch) = name
+            .chars()
+            .find(|&ch| !(ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'))
+        {
+            return Err(CrateNameError::BadChar {
+                name: name.to_string(),
+                ch,
+            });
+        }
+        let lower = name.to_ascii_lowercase();
+        if RESERVED_NAMES.contains(&lower.as_str()) {
+            return Err(CrateNameError::Reserved(name.to_string()));
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The form of this name used to detect collisions, see [`normalize`]
+    pub fn normalized(&self) -> String {
+        normalize(self.as_str())
+    }
+
+    /// The directory prefix cargo uses for this name in indices and downloads,
+    /// e.g. `1`, `2`, `3/s` or `se/rd`
+    pub fn prefix(&self) -> String {
+        let name = self.as_str();
+        match name.len() {
+            1 => "1".to_string(),
+            2 => "2".to_string(),
+            3 => format!("3/{}", &name[..1]),
+            _ => format!("{}/{}", &name[..2], &name[2..4]),
+        }
+    }
+}
+
+impl TryFrom<String> for CrateName {
+    type Error = CrateNameError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl From<CrateName> for String {
+    fn from(value: CrateName) -> Self {
+        value.0
+    }
+}
+
+impl std::str::FromStr for CrateName {
+    type Err = CrateNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl AsRef<str> for CrateName {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::ops::Deref for CrateName {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for CrateName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(name: &str) -> String {
+        CrateName::new(name).unwrap().prefix()
+    }
+
+    #[test]
+    fn valid_names() {
+        for name in ["a", "serde", "serde_json", "Foo-Bar", "x86_64", "tests"] {
+            assert_eq!(CrateName::new(name).unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn start_must_be_a_letter() {
+        assert_eq!(CrateName::new(""), Err(CrateNameError::Empty));
+        for name in ["1password", "_private", "-dash"] {
+            assert_eq!(
+                CrateName::new(name),
+                Err(CrateNameError::BadStart(name.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn only_ascii() {
+        assert_eq!(
+            CrateName::new("éclair"),
+            Err(CrateNameError::BadStart("éclair".to_string()))
+        );
+        assert_eq!(
+            CrateName::new("café"),
+            Err(CrateNameError::BadChar {
+                name: "café".to_string(),
+                ch: 'é'
+            })
+        );
+        assert_eq!(
+            CrateName::new("two words"),
+            Err(CrateNameError::BadChar {
+                name: "two words".to_string(),
+                ch: ' '
+            })
+        );
+    }
+
+    #[test]
+    fn length_limit() {
+        let longest = "a".repeat(MAX_NAME_LENGTH);
+        assert!(CrateName::new(&longest).is_ok());
+        let too_long = "a".repeat(MAX_NAME_LENGTH + 1);
+        assert_eq!(
+            CrateName::new(&too_long),
+            Err(CrateNameError::TooLong(too_long.clone()))
+        );
+        // The limit is in bytes, which for valid names are characters
+        let wide = "é".repeat(MAX_NAME_LENGTH / 2 + 1);
+        assert_eq!(
+            CrateName::new(&wide),
+            Err(CrateNameError::TooLong(wide.clone()))
+        );
+    }
+
+    #[test]
+    fn reserved_in_any_case() {
+        for name in [
+            "std",
+            "STD",
+            "Core",
+            "proc_macro",
+            "Proc-Macro",
+            "nul",
+            "CON",
+            "Lpt9",
+        ] {
+            assert_eq!(
+                CrateName::new(name),
+                Err(CrateNameError::Reserved(name.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        for pattern in ["*", "serde", "serde*", "s*", "Serde_*"] {
+            assert_eq!(check_pattern(pattern), Ok(()), "{pattern}");
+        }
+        assert_eq!(
+            check_pattern("1*"),
+            Err(CrateNameError::BadStart("1".to_string()))
+        );
+        assert_eq!(
+            check_pattern("*serde"),
+            Err(CrateNameError::BadStart("*serde".to_string()))
+        );
+        assert_eq!(
+            check_pattern("ser*de"),
+            Err(CrateNameError::BadChar {
+                name: "ser*de".to_string(),
+                ch: '*'
+            })
+        );
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(prefix("a"), "1");
+        assert_eq!(prefix("ab"), "2");
+        assert_eq!(prefix("abc"), "3/a");
+        assert_eq!(prefix("abcd"), "ab/cd");
+        assert_eq!(prefix("serde"), "se/rd");
+        assert_eq!(prefix("Serde"), "Se/rd");
+    }
+}
@@ -16,6 +16,8 @@ pub struct Metadata {
     pub deps: Vec<Dep>,
     /// The features defined by this package
     pub features: BTreeMap<String, Vec<String>>,
+    /// The description of the package
+    pub description: Option<String>,
     /// The package links field
     pub links: Option<String>,
     /// The minimum rust version this package needs
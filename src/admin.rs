@@ -0,0 +1,640 @@
+//! Administrative API, only usable by admins
+//!
+//! This covers the same ground as the `nabu user` command line, so that
+//! administration can be automated without shell access to the server.
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        Path as UrlPath,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use database::{
+    models::{AuditAction, Identity, Krate, KrateVer, NewAuditEvent, TEAM_PREFIX},
+    AsyncConnection, Connection, DatabaseErrorKind, DieselError,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::info;
+
+use crate::{
+    api::{
+        lookup_owners, CrateError, DeleteTokenRequest, NewTokenRequest, NewTokenResponse,
+        OkResponse, OwnersChangedResponse, OwnersRequest, TokenError, TokenInfo, TokensResponse,
+    },
+    audit::{self, SourceAddr},
+    auth::Admin,
+    error::ApiError,
+    state::AppState,
+};
+
+#[derive(Debug, Error)]
+enum AdminError {
+    #[error("Database error: {0}")]
+    Database(#[from] DieselError),
+    #[error("Unknown user: {0}")]
+    UnknownUser(String),
+    #[error("User {0} already exists")]
+    UserExists(String),
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("{name} version {vers} is already visible, so cannot be embargoed")]
+    AlreadyVisible { name: String, vers: String },
+    #[error(transparent)]
+    Token(#[from] TokenError),
+    #[error(transparent)]
+    Crate(#[from] CrateError),
+}
+
+impl From<JsonRejection> for AdminError {
+    fn from(value: JsonRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<PathRejection> for AdminError {
+    fn from(value: PathRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<AdminError> for ApiError {
+    fn from(value: AdminError) -> Self {
+        let code = match value {
+            AdminError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AdminError::UnknownUser(_) => StatusCode::NOT_FOUND,
+            AdminError::UserExists(_) => StatusCode::CONFLICT,
+            AdminError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AdminError::AlreadyVisible { .. } => StatusCode::CONFLICT,
+            // These already know how they should be reported
+            AdminError::Token(e) => return e.into(),
+            AdminError::Crate(e) => return e.into(),
+        };
+        ApiError::new(code, value.to_string())
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct UsersResponse {
+    users: Vec<UserInfo>,
+}
+
+#[derive(Serialize)]
+struct UserInfo {
+    id: i32,
+    name: String,
+    admin: bool,
+    disabled: bool,
+}
+
+impl From<Identity> for UserInfo {
+    fn from(user: Identity) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            admin: user.admin,
+            disabled: user.disabled,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PendingResponse {
+    versions: Vec<PendingVersion>,
+}
+
+#[derive(Serialize)]
+struct PendingVersion {
+    name: String,
+    vers: String,
+}
+
+#[derive(Deserialize)]
+struct EmbargoRequest {
+    /// RFC 3339 time at which the version becomes visible, or `None` to
+    /// release it immediately
+    visible_from: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NewUserRequest {
+    name: String,
+    #[serde(default)]
+    admin: bool,
+}
+
+async fn find_user(db: &mut Connection, name: &str) -> Result<Identity, AdminError> {
+    Identity::by_name(db, name)
+        .await?
+        .ok_or_else(|| AdminError::UnknownUser(name.to_string()))
+}
+
+async fn list_users(
+    Admin(_): Admin,
+    mut db: Connection,
+) -> Result<Json<UsersResponse>, AdminError> {
+    let users = Identity::all(&mut db).await?;
+    Ok(Json(UsersResponse {
+        users: users.into_iter().map(UserInfo::from).collect(),
+    }))
+}
+
+async fn create_user(
+    Admin(auth): Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    request: Result<Json<NewUserRequest>, JsonRejection>,
+) -> Result<Json<UserInfo>, AdminError> {
+    let Json(request) = request?;
+    if request.name.is_empty() || request.name.starts_with(TEAM_PREFIX) {
+        return Err(AdminError::BadRequest(format!(
+            "Invalid user name {:?}",
+            request.name
+        )));
+    }
+    let auth = &auth;
+    let source = &source;
+    let user = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let user = Identity::new(db, &request.name, request.admin)
+                    .await
+                    .map_err(|e| match e {
+                        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                            AdminError::UserExists(request.name.clone())
+                        }
+                        e => e.into(),
+                    })?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: user.admin.then_some("admin"),
+                    ..audit::event(auth, source, AuditAction::CreateUser)
+                }
+                .record(db)
+                .await?;
+                Ok::<_, AdminError>(user)
+            })
+        })
+        .await?;
+    info!("{} created user {}", auth.identity().name, user.name);
+    Ok(Json(UserInfo::from(user)))
+}
+
+async fn set_disabled(
+    auth: Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    name: String,
+    disabled: bool,
+) -> Result<Json<OkResponse>, AdminError> {
+    let Admin(auth) = auth;
+    let mut user = find_user(&mut db, &name).await?;
+    if disabled && user.id == auth.identity().id {
+        return Err(AdminError::BadRequest(
+            "You may not disable yourself".into(),
+        ));
+    }
+    let auth = &auth;
+    let source = &source;
+    let user = db
+        .transaction(|db| {
+            Box::pin(async move {
+                user.set_disabled(db, disabled).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    ..audit::event(
+                        auth,
+                        source,
+                        if disabled {
+                            AuditAction::DisableUser
+                        } else {
+                            AuditAction::EnableUser
+                        },
+                    )
+                }
+                .record(db)
+                .await?;
+                Ok::<_, DieselError>(user)
+            })
+        })
+        .await?;
+    info!(
+        "{} {} user {}",
+        auth.identity().name,
+        if disabled { "disabled" } else { "enabled" },
+        user.name
+    );
+    Ok(Json(OkResponse { ok: true }))
+}
+
+async fn disable_user(
+    auth: Admin,
+    source: SourceAddr,
+    db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath(name) = path?;
+    set_disabled(auth, source, db, name, true).await
+}
+
+async fn enable_user(
+    auth: Admin,
+    source: SourceAddr,
+    db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath(name) = path?;
+    set_disabled(auth, source, db, name, false).await
+}
+
+async fn list_user_tokens(
+    Admin(_): Admin,
+    mut db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+) -> Result<Json<TokensResponse>, AdminError> {
+    let UrlPath(name) = path?;
+    let user = find_user(&mut db, &name).await?;
+    let tokens = user.tokens(&mut db).await?;
+    Ok(Json(TokensResponse {
+        tokens: tokens.iter().map(TokenInfo::from).collect(),
+    }))
+}
+
+async fn create_user_token(
+    Admin(auth): Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+    request: Result<Json<NewTokenRequest>, JsonRejection>,
+) -> Result<Json<NewTokenResponse>, AdminError> {
+    let UrlPath(name) = path?;
+    let Json(request) = request?;
+    let (scopes, expires_at) = request.restrictions()?;
+    let user = find_user(&mut db, &name).await?;
+    let auth = &auth;
+    let source = &source;
+    let user = &user;
+    let (token, secret) = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let (token, secret) = user
+                    .new_token(db, &request.title, &scopes, expires_at)
+                    .await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&token.prefix),
+                    ..audit::event(auth, source, AuditAction::CreateToken)
+                }
+                .record(db)
+                .await?;
+                Ok::<_, DieselError>((token, secret))
+            })
+        })
+        .await?;
+    info!(
+        "{} created token {}... ({}) for {}",
+        auth.identity().name,
+        token.prefix,
+        token.title,
+        user.name
+    );
+    Ok(Json(NewTokenResponse {
+        token: secret,
+        info: TokenInfo::from(&token),
+    }))
+}
+
+async fn delete_user_token(
+    Admin(auth): Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+    request: Result<Json<DeleteTokenRequest>, JsonRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath(name) = path?;
+    let Json(request) = request?;
+    let user = find_user(&mut db, &name).await?;
+    let auth = &auth;
+    let source = &source;
+    let user = &user;
+    let token = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let token = user
+                    .delete_token(db, request.id)
+                    .await?
+                    .ok_or_else(|| TokenError::UnknownToken(request.id.to_string()))?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&token.prefix),
+                    ..audit::event(auth, source, AuditAction::DeleteToken)
+                }
+                .record(db)
+                .await?;
+                Ok::<_, AdminError>(token)
+            })
+        })
+        .await?;
+    info!(
+        "{} deleted token {}... of {}",
+        auth.identity().name,
+        token.prefix,
+        user.name
+    );
+    Ok(Json(OkResponse { ok: true }))
+}
+
+/// Replace every owner of a crate with the given users and teams
+async fn transfer_crate(
+    Admin(auth): Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+    request: Result<Json<OwnersRequest>, JsonRejection>,
+) -> Result<Json<OwnersChangedResponse>, AdminError> {
+    let UrlPath(name) = path?;
+    let Json(request) = request?;
+    if request.users.is_empty() {
+        return Err(CrateError::LastOwner(name).into());
+    }
+    let krate = Krate::by_name(&mut db, &name)
+        .await?
+        .ok_or(CrateError::UnknownCrate(name))?;
+    let names = request.users.join(", ");
+    let auth = &auth;
+    let source = &source;
+    let krate = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let owners = lookup_owners(db, &request.users).await?;
+                let old_users = krate.owners(db).await?;
+                let old_teams = krate.team_owners(db).await?;
+                for user in old_users {
+                    krate.remove_owner(db, &user).await?;
+                    NewAuditEvent {
+                        subject: Some(user.id),
+                        krate: Some(&krate.name),
+                        ..audit::event(auth, source, AuditAction::RemoveOwner)
+                    }
+                    .record(db)
+                    .await?;
+                }
+                for team in old_teams {
+                    krate.remove_team_owner(db, &team).await?;
+                    NewAuditEvent {
+                        krate: Some(&krate.name),
+                        detail: Some(&team.login()),
+                        ..audit::event(auth, source, AuditAction::RemoveOwner)
+                    }
+                    .record(db)
+                    .await?;
+                }
+                for owner in &owners {
+                    let login = owner.login();
+                    owner.add_to(db, &krate).await?;
+                    owner
+                        .audit_event(auth, source, AuditAction::AddOwner, &krate, &login)
+                        .record(db)
+                        .await?;
+                }
+                Ok::<_, AdminError>(krate)
+            })
+        })
+        .await?;
+    info!(
+        "{} transferred {} to {names}",
+        auth.identity().name,
+        krate.name
+    );
+    Ok(Json(OwnersChangedResponse {
+        ok: true,
+        msg: format!("{} is now owned by {names}", krate.name),
+    }))
+}
+
+async fn force_yank(
+    auth: Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    name: String,
+    vers: String,
+    yanked: bool,
+) -> Result<Json<OkResponse>, AdminError> {
+    let Admin(auth) = auth;
+    let krate = Krate::by_name(&mut db, &name)
+        .await?
+        .ok_or_else(|| CrateError::UnknownCrate(name.clone()))?;
+    let mut version = krate
+        .version(&mut db, &vers)
+        .await?
+        .ok_or(CrateError::UnknownVersion { name, vers })?;
+    let auth = &auth;
+    let source = &source;
+    let krate = &krate;
+    let version = db
+        .transaction(|db| {
+            Box::pin(async move {
+                version.set_yanked(db, yanked).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    version: Some(&version.ver),
+                    ..audit::event(
+                        auth,
+                        source,
+                        if yanked {
+                            AuditAction::Yank
+                        } else {
+                            AuditAction::Unyank
+                        },
+                    )
+                }
+                .record(db)
+                .await?;
+                Ok::<_, DieselError>(version)
+            })
+        })
+        .await?;
+    info!(
+        "{} forced {} of {} version {}",
+        auth.identity().name,
+        if yanked { "yank" } else { "unyank" },
+        krate.name,
+        version.ver
+    );
+    Ok(Json(OkResponse { ok: true }))
+}
+
+async fn yank_crate(
+    auth: Admin,
+    source: SourceAddr,
+    db: Connection,
+    path: Result<UrlPath<(String, String)>, PathRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath((name, vers)) = path?;
+    force_yank(auth, source, db, name, vers, true).await
+}
+
+async fn unyank_crate(
+    auth: Admin,
+    source: SourceAddr,
+    db: Connection,
+    path: Result<UrlPath<(String, String)>, PathRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath((name, vers)) = path?;
+    force_yank(auth, source, db, name, vers, false).await
+}
+
+/// Every version awaiting approval
+async fn list_pending(
+    Admin(_): Admin,
+    mut db: Connection,
+) -> Result<Json<PendingResponse>, AdminError> {
+    let pending = KrateVer::pending(&mut db).await?;
+    Ok(Json(PendingResponse {
+        versions: pending
+            .into_iter()
+            .map(|(krate, version)| PendingVersion {
+                name: krate.name,
+                vers: version.ver,
+            })
+            .collect(),
+    }))
+}
+
+/// Expose a version which is pending approval
+async fn approve_crate(
+    Admin(auth): Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    path: Result<UrlPath<(String, String)>, PathRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath((name, vers)) = path?;
+    let krate = Krate::by_name(&mut db, &name)
+        .await?
+        .ok_or_else(|| CrateError::UnknownCrate(name.clone()))?;
+    let mut version = krate
+        .version(&mut db, &vers)
+        .await?
+        .ok_or(CrateError::UnknownVersion { name, vers })?;
+    if !version.exposed {
+        info!(
+            "{} approved {} version {}",
+            auth.identity().name,
+            krate.name,
+            version.ver
+        );
+        let auth = &auth;
+        let source = &source;
+        db.transaction(|db| {
+            Box::pin(async move {
+                version.expose(db).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    version: Some(&version.ver),
+                    ..audit::event(auth, source, AuditAction::Approve)
+                }
+                .record(db)
+                .await
+            })
+        })
+        .await?;
+    }
+    Ok(Json(OkResponse { ok: true }))
+}
+
+/// Hide a version until the requested time, or lift its embargo
+async fn embargo_crate(
+    Admin(auth): Admin,
+    source: SourceAddr,
+    mut db: Connection,
+    path: Result<UrlPath<(String, String)>, PathRejection>,
+    request: Result<Json<EmbargoRequest>, JsonRejection>,
+) -> Result<Json<OkResponse>, AdminError> {
+    let UrlPath((name, vers)) = path?;
+    let Json(request) = request?;
+    let visible_from = request
+        .visible_from
+        .as_deref()
+        .map(|when| OffsetDateTime::parse(when, &Rfc3339))
+        .transpose()
+        .map_err(|e| AdminError::BadRequest(format!("Bad embargo time: {e}")))?;
+    let krate = Krate::by_name(&mut db, &name)
+        .await?
+        .ok_or_else(|| CrateError::UnknownCrate(name.clone()))?;
+    let mut version = krate
+        .version(&mut db, &vers)
+        .await?
+        .ok_or(CrateError::UnknownVersion { name, vers })?;
+    // Those who could see the version have already fetched it
+    if visible_from.is_some() && version.is_visible() {
+        return Err(AdminError::AlreadyVisible {
+            name: krate.name,
+            vers: version.ver,
+        });
+    }
+    let auth = &auth;
+    let source = &source;
+    let krate = &krate;
+    let detail = request.visible_from.as_deref();
+    let version = db
+        .transaction(|db| {
+            Box::pin(async move {
+                version.set_visible_from(db, visible_from).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    version: Some(&version.ver),
+                    detail,
+                    ..audit::event(
+                        auth,
+                        source,
+                        if visible_from.is_some() {
+                            AuditAction::Embargo
+                        } else {
+                            AuditAction::LiftEmbargo
+                        },
+                    )
+                }
+                .record(db)
+                .await?;
+                Ok::<_, DieselError>(version)
+            })
+        })
+        .await?;
+    info!(
+        "{} set the embargo on {} version {} to {visible_from:?}",
+        auth.identity().name,
+        krate.name,
+        version.ver
+    );
+    Ok(Json(OkResponse { ok: true }))
+}
+
+pub fn router(_state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/:name/disable", put(disable_user))
+        .route("/users/:name/enable", put(enable_user))
+        .route(
+            "/users/:name/tokens",
+            get(list_user_tokens)
+                .post(create_user_token)
+                .delete(delete_user_token),
+        )
+        .route("/crates/:name/transfer", post(transfer_crate))
+        .route("/crates/:name/:version/yank", delete(yank_crate))
+        .route("/crates/:name/:version/unyank", put(unyank_crate))
+        .route("/pending", get(list_pending))
+        .route("/crates/:name/:version/approve", put(approve_crate))
+        .route("/crates/:name/:version/embargo", put(embargo_crate))
+}
@@ -1,56 +1,368 @@
 use std::path::{Path, PathBuf};
 
-use axum::extract::State;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::middleware;
 use axum::response::Response;
+use axum::routing::{delete, get};
 use axum::Json;
 use axum::{body::Bytes, http::StatusCode, response::IntoResponse, routing::put, Router};
 use bytes::Buf;
-use database::models::Krate;
-use database::Connection;
+use database::models::{
+    AuditAction, Identity, Krate, NewAuditEvent, Scope, Team, Token, TokenScopes, TEAM_PREFIX,
+};
+use database::{AsyncConnection, AsyncPgConnection, Connection, DatabaseErrorKind, DieselError};
+use metadata::name::{check_pattern, CrateName, CrateNameError};
 use metadata::{index, publish};
-use serde::Serialize;
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::info;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+use tracing::{error, info};
 
+use crate::audit::{self, SourceAddr};
+use crate::auth;
 use crate::configuration::Configuration;
-use crate::{auth::Authentication, state::AppState};
+use crate::error::ApiError;
+use crate::{
+    auth::{Authentication, ClaimMismatch, MissingScope, Mutation, Restricted},
+    state::AppState,
+};
 
 #[derive(Debug, Error)]
 enum PublishError {
     #[error("Database error: {0}")]
-    Database(#[from] database::DieselError),
+    Database(#[from] DieselError),
     #[error("Invalid body length, Needed {need} but had {had}")]
     InvalidBodyLength { need: usize, had: usize },
     #[error("Bad metadata length: {0}")]
     BadMetadataLength(usize),
     #[error("Failure during deserialisation: {0}")]
     Deserialise(#[from] serde_path_to_error::Error<serde_json::Error>),
+    #[error("Invalid crate name: {0}")]
+    BadCrateName(#[from] CrateNameError),
+    #[error("Crate name {name} is too similar to the existing crate {existing}")]
+    NameCollision { name: String, existing: String },
     #[error("Some dependencies unmet: {0:?}")]
     UnmetDeps(Vec<String>),
     #[error("IO error storing crate: {0}")]
     IO(#[from] std::io::Error),
+    #[error("You do not have permission to publish new versions of {0}")]
+    NotOwner(String),
+    #[error(transparent)]
+    Scope(#[from] MissingScope),
+    #[error(transparent)]
+    Claim(#[from] ClaimMismatch),
+    #[error("Version {vers} of {name} has already been published")]
+    DuplicateVersion { name: String, vers: String },
+    #[error("Invalid version {vers}: {error}")]
+    BadVersion { vers: String, error: semver::Error },
+    #[error(
+        "Version {vers} of {name} differs from the existing {existing} only in build metadata"
+    )]
+    BuildMetadataDuplicate {
+        name: String,
+        vers: String,
+        existing: String,
+    },
+    #[error("Bad embargo: {0}")]
+    BadEmbargo(String),
 }
 
-#[derive(Serialize)]
-struct GenericError {
-    error: String,
+impl From<PublishError> for ApiError {
+    fn from(value: PublishError) -> Self {
+        let code = match &value {
+            PublishError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PublishError::InvalidBodyLength { .. } => StatusCode::BAD_REQUEST,
+            PublishError::BadMetadataLength(_) => StatusCode::BAD_REQUEST,
+            PublishError::Deserialise(_) => StatusCode::BAD_REQUEST,
+            PublishError::BadCrateName(_) => StatusCode::BAD_REQUEST,
+            PublishError::NameCollision { .. } => StatusCode::BAD_REQUEST,
+            PublishError::UnmetDeps(_) => StatusCode::BAD_REQUEST,
+            PublishError::IO(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PublishError::NotOwner(_) => StatusCode::FORBIDDEN,
+            PublishError::Scope(_) => StatusCode::FORBIDDEN,
+            PublishError::Claim(_) => StatusCode::FORBIDDEN,
+            PublishError::DuplicateVersion { .. } => StatusCode::CONFLICT,
+            PublishError::BadVersion { .. } => StatusCode::BAD_REQUEST,
+            PublishError::BuildMetadataDuplicate { .. } => StatusCode::CONFLICT,
+            PublishError::BadEmbargo(_) => StatusCode::BAD_REQUEST,
+        };
+        ApiError::new(code, value.to_string())
+    }
 }
 
 impl IntoResponse for PublishError {
     fn into_response(self) -> Response {
-        let code = match &self {
-            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::InvalidBodyLength { .. } => StatusCode::BAD_REQUEST,
-            Self::BadMetadataLength(_) => StatusCode::BAD_REQUEST,
-            Self::Deserialise(_) => StatusCode::BAD_REQUEST,
-            Self::UnmetDeps(_) => StatusCode::BAD_REQUEST,
-            Self::IO(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum CrateError {
+    #[error("Database error: {0}")]
+    Database(#[from] DieselError),
+    #[error("Unknown crate: {0}")]
+    UnknownCrate(String),
+    #[error("Unknown version {vers} of crate {name}")]
+    UnknownVersion { name: String, vers: String },
+    #[error("You do not own the crate {0}")]
+    NotOwner(String),
+    #[error(transparent)]
+    Scope(#[from] MissingScope),
+    #[error(transparent)]
+    Claim(#[from] ClaimMismatch),
+    #[error("Unknown user: {0}")]
+    UnknownUser(String),
+    #[error("Unknown team: {0}")]
+    UnknownTeam(String),
+    #[error("Cannot remove every owner of the crate {0}")]
+    LastOwner(String),
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+}
+
+impl From<JsonRejection> for CrateError {
+    fn from(value: JsonRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<QueryRejection> for CrateError {
+    fn from(value: QueryRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<PathRejection> for CrateError {
+    fn from(value: PathRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<CrateError> for ApiError {
+    fn from(value: CrateError) -> Self {
+        let code = match &value {
+            CrateError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CrateError::UnknownCrate(_) => StatusCode::NOT_FOUND,
+            CrateError::UnknownVersion { .. } => StatusCode::NOT_FOUND,
+            CrateError::NotOwner(_) => StatusCode::FORBIDDEN,
+            CrateError::Scope(_) => StatusCode::FORBIDDEN,
+            CrateError::Claim(_) => StatusCode::FORBIDDEN,
+            CrateError::UnknownUser(_) => StatusCode::BAD_REQUEST,
+            CrateError::UnknownTeam(_) => StatusCode::BAD_REQUEST,
+            CrateError::LastOwner(_) => StatusCode::BAD_REQUEST,
+            CrateError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        };
+        ApiError::new(code, value.to_string())
+    }
+}
+
+impl IntoResponse for CrateError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum TokenError {
+    #[error("Database error: {0}")]
+    Database(#[from] DieselError),
+    #[error("Unknown token: {0}")]
+    UnknownToken(String),
+    #[error("A token may not create a token with more permissions than itself")]
+    Escalation,
+    #[error(transparent)]
+    Restricted(#[from] Restricted),
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+}
+
+impl From<JsonRejection> for TokenError {
+    fn from(value: JsonRejection) -> Self {
+        Self::BadRequest(value.body_text())
+    }
+}
+
+impl From<TokenError> for ApiError {
+    fn from(value: TokenError) -> Self {
+        let code = match &value {
+            TokenError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            TokenError::UnknownToken(_) => StatusCode::NOT_FOUND,
+            TokenError::Escalation => StatusCode::FORBIDDEN,
+            TokenError::Restricted(_) => StatusCode::FORBIDDEN,
+            TokenError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        };
+        ApiError::new(code, value.to_string())
+    }
+}
+
+impl IntoResponse for TokenError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct OkResponse {
+    pub(crate) ok: bool,
+}
+
+#[derive(Serialize)]
+struct OwnersResponse {
+    users: Vec<OwnerUser>,
+}
+
+#[derive(Serialize)]
+struct OwnerUser {
+    id: i32,
+    login: String,
+    name: Option<String>,
+    kind: &'static str,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OwnersRequest {
+    pub(crate) users: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OwnersChangedResponse {
+    pub(crate) ok: bool,
+    pub(crate) msg: String,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    per_page: Option<usize>,
+    page: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    crates: Vec<SearchResult>,
+    meta: SearchMeta,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    name: String,
+    max_version: String,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct SearchMeta {
+    total: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TokensResponse {
+    pub(crate) tokens: Vec<TokenInfo>,
+}
+
+/// A token as shown to its owner, the secret itself is never available again
+#[derive(Serialize)]
+pub(crate) struct TokenInfo {
+    id: i32,
+    prefix: String,
+    title: String,
+    created_at: String,
+    expires_at: Option<String>,
+    last_used_at: Option<String>,
+    expired: bool,
+    endpoint_scopes: Option<Vec<String>>,
+    crate_scopes: Option<Vec<String>>,
+}
+
+/// Format a time in UTC as RFC 3339, for both API responses and the command line
+pub(crate) fn format_time(when: OffsetDateTime) -> String {
+    when.to_offset(UtcOffset::UTC)
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| when.to_string())
+}
+
+impl From<&Token> for TokenInfo {
+    fn from(token: &Token) -> Self {
+        let strings = |v: Vec<&str>| v.into_iter().map(String::from).collect();
+        Self {
+            id: token.id,
+            prefix: token.prefix.clone(),
+            title: token.title.clone(),
+            created_at: format_time(token.created_at),
+            expires_at: token.expires_at.map(format_time),
+            last_used_at: token.last_used_at.map(format_time),
+            expired: token.is_expired(),
+            endpoint_scopes: token.endpoint_scopes().map(strings),
+            crate_scopes: token.crate_scopes().map(strings),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct NewTokenRequest {
+    pub(crate) title: String,
+    endpoint_scopes: Option<Vec<String>>,
+    crate_scopes: Option<Vec<String>>,
+    /// RFC 3339 time at which the token stops working
+    expires_at: Option<String>,
+}
+
+impl NewTokenRequest {
+    /// Validate the scopes and expiry time requested
+    pub(crate) fn restrictions(&self) -> Result<(TokenScopes, Option<OffsetDateTime>), TokenError> {
+        let endpoints = self
+            .endpoint_scopes
+            .as_ref()
+            .map(|scopes| {
+                scopes
+                    .iter()
+                    .map(|scope| scope.parse())
+                    .collect::<Result<Vec<Scope>, _>>()
+            })
+            .transpose()
+            .map_err(TokenError::BadRequest)?;
+        if let Some(crates) = &self.crate_scopes {
+            for pattern in crates {
+                check_pattern(pattern).map_err(|e| TokenError::BadRequest(e.to_string()))?;
+            }
+        }
+        let expires_at = self
+            .expires_at
+            .as_deref()
+            .map(|when| OffsetDateTime::parse(when, &Rfc3339))
+            .transpose()
+            .map_err(|e| TokenError::BadRequest(format!("Bad expiry time: {e}")))?;
+        let scopes = TokenScopes {
+            endpoints,
+            crates: self.crate_scopes.clone(),
         };
-        let msg = self.to_string();
-        (code, Json(GenericError { error: msg })).into_response()
+        Ok((scopes, expires_at))
     }
 }
 
+#[derive(Serialize)]
+pub(crate) struct NewTokenResponse {
+    /// The secret, which is only ever shown this once
+    pub(crate) token: String,
+    #[serde(flatten)]
+    pub(crate) info: TokenInfo,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeleteTokenRequest {
+    /// The token's id, the prefix is only for display and need not be unique
+    pub(crate) id: i32,
+}
+
+#[derive(Deserialize)]
+struct PublishQuery {
+    /// RFC 3339 time before which the new version is hidden from the index
+    visible_from: Option<String>,
+}
+
 #[derive(Default, Serialize)]
 struct PublishResponse {
     warnings: PublishWarnings,
@@ -63,23 +375,8 @@ struct PublishWarnings {
     other: Vec<String>,
 }
 
-fn make_crate_filename(base: &Path, krate: &str, version: &str) -> std::io::Result<PathBuf> {
-    let container = match krate.len() {
-        0 => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "empty crate name",
-            ))
-        }
-        1 => "1".to_string(),
-        2 => "2".to_string(),
-        3 => format!("3/{}", krate.chars().next().unwrap()),
-        _ => {
-            let chars: Vec<char> = krate.chars().take(4).collect();
-            format!("{}{}/{}{}", chars[0], chars[1], chars[2], chars[3])
-        }
-    };
-    let mut dir_to_make = base.join(container);
+fn make_crate_filename(base: &Path, krate: &CrateName, version: &str) -> std::io::Result<PathBuf> {
+    let mut dir_to_make = base.join(krate.prefix());
     std::fs::create_dir_all(&dir_to_make)?;
     dir_to_make.push(format!("{krate}-{version}.crate"));
     Ok(dir_to_make)
@@ -88,10 +385,21 @@ fn make_crate_filename(base: &Path, krate: &str, version: &str) -> std::io::Resu
 async fn publish_crate(
     mut db: Connection,
     auth: Authentication,
+    source: SourceAddr,
     State(config): State<Configuration>,
+    query: Result<Query<PublishQuery>, QueryRejection>,
     mut body: Bytes,
 ) -> Result<Json<PublishResponse>, PublishError> {
     info!("Begin publish flow...");
+    // Cargo never asks for an embargo, but release tooling may
+    let Query(query) = query.map_err(|e| PublishError::BadEmbargo(e.body_text()))?;
+    let visible_from = query
+        .visible_from
+        .as_deref()
+        .map(|when| OffsetDateTime::parse(when, &Rfc3339))
+        .transpose()
+        .map_err(|e| PublishError::BadEmbargo(e.to_string()))?;
+
     // Step one, acquire the metadata
     if body.len() < 4 {
         return Err(PublishError::InvalidBodyLength {
@@ -121,8 +429,19 @@ async fn publish_crate(
     let mut deser = serde_json::Deserializer::from_reader(metaraw.reader());
 
     let meta: publish::Metadata = serde_path_to_error::deserialize(&mut deser)?;
+    let description = meta.description.clone();
+    let krate_name = CrateName::new(&meta.name)?;
+    let version = Version::parse(&meta.vers).map_err(|e| PublishError::BadVersion {
+        vers: meta.vers.clone(),
+        error: e,
+    })?;
 
     let cksum = sha256::digest(body.as_ref());
+    auth.require_mutation(Mutation::Publish {
+        name: &meta.name,
+        vers: &meta.vers,
+        cksum: &cksum,
+    })?;
     let entry = index::Entry::from_publish(meta, cksum);
 
     let mut bad_deps = Vec::new();
@@ -151,20 +470,573 @@ async fn publish_crate(
         ));
     }
 
-    // At this point we can be happy that the upload is good
+    // Check the name does not collide with a differently spelled crate, that
+    // the publisher is permitted to add versions to an existing crate, and
+    // that this version is not already present
+    if let Some(krate) = Krate::by_normalized_name(&mut db, &krate_name).await? {
+        if krate.name != krate_name.as_str() {
+            return Err(PublishError::NameCollision {
+                name: krate_name.to_string(),
+                existing: krate.name,
+            });
+        }
+        if !krate.can_publish(&mut db, auth.identity()).await? {
+            return Err(PublishError::NotOwner(krate.name));
+        }
+        auth.require_scope(Scope::PublishUpdate, &krate.name)?;
+        if let Some(existing) = krate.version_ignoring_build(&mut db, &version).await? {
+            if existing.ver == entry.vers {
+                return Err(PublishError::DuplicateVersion {
+                    name: krate.name,
+                    vers: entry.vers,
+                });
+            }
+            return Err(PublishError::BuildMetadataDuplicate {
+                name: krate.name,
+                vers: entry.vers,
+                existing: existing.ver,
+            });
+        }
+    } else {
+        auth.require_scope(Scope::PublishNew, &krate_name)?;
+    }
+
+    // At this point we can be happy that the upload is good, so stage the
+    // crate file where it cannot be downloaded until it is published
+
+    let crate_filename = make_crate_filename(config.crate_path(), &krate_name, &entry.vers)?;
+    let staged = tempfile::Builder::new()
+        .prefix(".upload-")
+        .tempfile_in(config.upload_path())?;
+
+    tokio::fs::write(staged.path(), body).await?;
+
+    // Temporary files are private by default, but crates are served to anyone
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        staged
+            .as_file()
+            .set_permissions(std::fs::Permissions::from_mode(0o644))?;
+    }
+
+    let auth = &auth;
+    let source = &source;
+    let require_approval = config.require_approval();
+    let (pending, stored) = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let krate = match Krate::by_name_or_new(db, &entry.name, auth.identity()).await {
+                    // A concurrent publish created this crate, or one whose
+                    // name collides with it, since the checks above
+                    Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                        match Krate::by_normalized_name(db, &entry.name).await? {
+                            Some(existing) if existing.name != entry.name => {
+                                return Err(PublishError::NameCollision {
+                                    name: entry.name.clone(),
+                                    existing: existing.name,
+                                });
+                            }
+                            _ => Krate::by_name_or_new(db, &entry.name, auth.identity()).await?,
+                        }
+                    }
+                    krate => krate?,
+                };
+                let mut krate = krate.ok_or_else(|| PublishError::NotOwner(entry.name.clone()))?;
+
+                let pending = krate.needs_approval(require_approval);
+                let version = krate
+                    .new_version(db, &entry, !pending, visible_from)
+                    .await
+                    .map_err(|e| match e {
+                        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                            PublishError::DuplicateVersion {
+                                name: entry.name.clone(),
+                                vers: entry.vers.clone(),
+                            }
+                        }
+                        e => e.into(),
+                    })?;
+
+                krate.set_description(db, description.as_deref()).await?;
+                NewAuditEvent {
+                    krate: Some(&entry.name),
+                    version: Some(&entry.vers),
+                    ..audit::event(auth, source, AuditAction::Publish)
+                }
+                .record(db)
+                .await?;
+
+                Ok::<_, PublishError>((pending, version))
+            })
+        })
+        .await?;
+
+    // Only move the crate into place once the database rows are committed.
+    // If the transaction failed the staged file is dropped and so removed,
+    // and if the move fails the version must not be left without its file
+    if let Err(e) = staged.persist(&crate_filename) {
+        error!(
+            "Unable to store {krate_name} {}, removing the version: {}",
+            stored.ver, e.error
+        );
+        stored.delete(&mut db).await?;
+        return Err(e.error.into());
+    }
+
+    let mut response = PublishResponse::default();
+    if pending {
+        response.warnings.other.push(format!(
+            "{krate_name} {version} is pending approval by an admin, \
+             and will not appear in the index until it is approved"
+        ));
+    }
+    if let Some(when) = visible_from.filter(|when| *when > OffsetDateTime::now_utc()) {
+        response.warnings.other.push(format!(
+            "{krate_name} {version} is embargoed, and will not appear in the index until {}",
+            format_time(when)
+        ));
+    }
+    Ok(Json(response))
+}
 
-    let crate_filename = make_crate_filename(config.crate_path(), &entry.name, &entry.vers)?;
+async fn search_crates(
+    mut db: Connection,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<Json<SearchResponse>, CrateError> {
+    let Query(query) = query?;
+    let per_page = query.per_page.unwrap_or(10).clamp(1, 100);
+    let offset = query
+        .page
+        .unwrap_or(1)
+        .max(1)
+        .saturating_sub(1)
+        .saturating_mul(per_page);
+    let needle = query.q.trim().to_lowercase();
+    let (hits, total) = Krate::search(
+        &mut db,
+        &needle,
+        per_page as i64,
+        i64::try_from(offset).unwrap_or(i64::MAX),
+    )
+    .await?;
+    let crates = hits
+        .into_iter()
+        .map(|hit| SearchResult {
+            name: hit.name,
+            max_version: hit.max_version.unwrap_or_default(),
+            description: hit.description.unwrap_or_default(),
+        })
+        .collect();
+    Ok(Json(SearchResponse {
+        crates,
+        meta: SearchMeta { total },
+    }))
+}
 
-    tokio::fs::write(crate_filename, body).await?;
+async fn owned_krate(
+    db: &mut Connection,
+    auth: &Authentication,
+    name: &str,
+) -> Result<Krate, CrateError> {
+    let krate = Krate::by_name(db, name)
+        .await?
+        .ok_or_else(|| CrateError::UnknownCrate(name.to_string()))?;
+    if !krate.owned_by(db, auth.identity()).await? {
+        return Err(CrateError::NotOwner(krate.name));
+    }
+    Ok(krate)
+}
 
-    let krate = Krate::by_name_or_new(&mut db, &entry.name, auth.identity()).await?;
+async fn set_yanked(
+    mut db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    name: String,
+    vers: String,
+    yanked: bool,
+) -> Result<Json<OkResponse>, CrateError> {
+    let mutation = if yanked {
+        Mutation::Yank {
+            name: &name,
+            vers: &vers,
+        }
+    } else {
+        Mutation::Unyank {
+            name: &name,
+            vers: &vers,
+        }
+    };
+    auth.require_mutation(mutation)?;
+    let krate = owned_krate(&mut db, &auth, &name).await?;
+    auth.require_scope(Scope::Yank, &krate.name)?;
+    let mut version = krate
+        .version(&mut db, &vers)
+        .await?
+        .ok_or(CrateError::UnknownVersion { name, vers })?;
+    let auth = &auth;
+    let source = &source;
+    db.transaction(|db| {
+        Box::pin(async move {
+            version.set_yanked(db, yanked).await?;
+            NewAuditEvent {
+                krate: Some(&krate.name),
+                version: Some(&version.ver),
+                ..audit::event(
+                    auth,
+                    source,
+                    if yanked {
+                        AuditAction::Yank
+                    } else {
+                        AuditAction::Unyank
+                    },
+                )
+            }
+            .record(db)
+            .await
+        })
+    })
+    .await?;
+    Ok(Json(OkResponse { ok: true }))
+}
 
-    let _vers = krate.new_version(&mut db, &entry).await?;
+async fn yank_crate(
+    db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    path: Result<UrlPath<(String, String)>, PathRejection>,
+) -> Result<Json<OkResponse>, CrateError> {
+    let UrlPath((name, vers)) = path?;
+    info!("Yanking {name} version {vers}");
+    set_yanked(db, auth, source, name, vers, true).await
+}
 
-    // At some point return any warnings
-    Ok(Json(PublishResponse::default()))
+async fn unyank_crate(
+    db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    path: Result<UrlPath<(String, String)>, PathRejection>,
+) -> Result<Json<OkResponse>, CrateError> {
+    let UrlPath((name, vers)) = path?;
+    info!("Unyanking {name} version {vers}");
+    set_yanked(db, auth, source, name, vers, false).await
 }
 
-pub fn router(_state: &AppState) -> Router<AppState> {
-    Router::new().route("/v1/crates/new", put(publish_crate))
+async fn list_owners(
+    mut db: Connection,
+    path: Result<UrlPath<String>, PathRejection>,
+) -> Result<Json<OwnersResponse>, CrateError> {
+    let UrlPath(name) = path?;
+    let krate = Krate::by_name(&mut db, &name)
+        .await?
+        .ok_or(CrateError::UnknownCrate(name))?;
+    let mut users: Vec<_> = krate
+        .owners(&mut db)
+        .await?
+        .into_iter()
+        .map(|owner| OwnerUser {
+            id: owner.id,
+            login: owner.name,
+            name: None,
+            kind: "user",
+        })
+        .collect();
+    users.extend(
+        krate
+            .team_owners(&mut db)
+            .await?
+            .into_iter()
+            .map(|team| OwnerUser {
+                id: team.id,
+                login: team.login(),
+                name: None,
+                kind: "team",
+            }),
+    );
+    Ok(Json(OwnersResponse { users }))
+}
+
+/// A crate owner, either a single user or a team all of whose members
+/// count as owners
+pub(crate) enum Owner {
+    User(Identity),
+    Team(Team),
+}
+
+impl Owner {
+    pub(crate) fn login(&self) -> String {
+        match self {
+            Owner::User(user) => user.name.clone(),
+            Owner::Team(team) => team.login(),
+        }
+    }
+
+    pub(crate) async fn add_to(
+        &self,
+        db: &mut AsyncPgConnection,
+        krate: &Krate,
+    ) -> Result<(), CrateError> {
+        match self {
+            Owner::User(user) => krate.add_owner(db, user).await?,
+            Owner::Team(team) => krate.add_team_owner(db, team).await?,
+        };
+        Ok(())
+    }
+
+    pub(crate) async fn remove_from(
+        &self,
+        db: &mut AsyncPgConnection,
+        krate: &Krate,
+    ) -> Result<(), CrateError> {
+        match self {
+            Owner::User(user) => krate.remove_owner(db, user).await?,
+            Owner::Team(team) => krate.remove_team_owner(db, team).await?,
+        };
+        Ok(())
+    }
+
+    /// An audit event for a change to this owner of the crate
+    pub(crate) fn audit_event<'a>(
+        &self,
+        auth: &Authentication,
+        source: &'a SourceAddr,
+        action: AuditAction,
+        krate: &'a Krate,
+        login: &'a str,
+    ) -> NewAuditEvent<'a> {
+        match self {
+            Owner::User(user) => NewAuditEvent {
+                subject: Some(user.id),
+                krate: Some(&krate.name),
+                ..audit::event(auth, source, action)
+            },
+            Owner::Team(_) => NewAuditEvent {
+                krate: Some(&krate.name),
+                detail: Some(login),
+                ..audit::event(auth, source, action)
+            },
+        }
+    }
+}
+
+/// Find the users, or `team:` prefixed teams, with the given logins
+pub(crate) async fn lookup_owners(
+    db: &mut AsyncPgConnection,
+    logins: &[String],
+) -> Result<Vec<Owner>, CrateError> {
+    let mut owners = Vec::with_capacity(logins.len());
+    for login in logins {
+        let owner = match login.strip_prefix(TEAM_PREFIX) {
+            Some(team) => Owner::Team(
+                Team::by_name(db, team)
+                    .await?
+                    .ok_or_else(|| CrateError::UnknownTeam(team.to_string()))?,
+            ),
+            None => Owner::User(
+                Identity::by_name(db, login)
+                    .await?
+                    .ok_or_else(|| CrateError::UnknownUser(login.clone()))?,
+            ),
+        };
+        owners.push(owner);
+    }
+    Ok(owners)
+}
+
+async fn add_owners(
+    mut db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    path: Result<UrlPath<String>, PathRejection>,
+    request: Result<Json<OwnersRequest>, JsonRejection>,
+) -> Result<Json<OwnersChangedResponse>, CrateError> {
+    let UrlPath(name) = path?;
+    let Json(request) = request?;
+    auth.require_mutation(Mutation::Owners { name: &name })?;
+    let krate = owned_krate(&mut db, &auth, &name).await?;
+    auth.require_scope(Scope::ChangeOwners, &krate.name)?;
+    let owners = lookup_owners(&mut db, &request.users).await?;
+    let auth = &auth;
+    let source = &source;
+    let krate = db
+        .transaction(|db| {
+            Box::pin(async move {
+                for owner in &owners {
+                    let login = owner.login();
+                    info!("Adding {login} as an owner of {}", krate.name);
+                    owner.add_to(db, &krate).await?;
+                    owner
+                        .audit_event(auth, source, AuditAction::AddOwner, &krate, &login)
+                        .record(db)
+                        .await?;
+                }
+                Ok::<_, CrateError>(krate)
+            })
+        })
+        .await?;
+    Ok(Json(OwnersChangedResponse {
+        ok: true,
+        msg: format!(
+            "{} added as owners of {}",
+            request.users.join(", "),
+            krate.name
+        ),
+    }))
+}
+
+async fn remove_owners(
+    mut db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    path: Result<UrlPath<String>, PathRejection>,
+    request: Result<Json<OwnersRequest>, JsonRejection>,
+) -> Result<Json<OwnersChangedResponse>, CrateError> {
+    let UrlPath(name) = path?;
+    let Json(request) = request?;
+    auth.require_mutation(Mutation::Owners { name: &name })?;
+    let krate = owned_krate(&mut db, &auth, &name).await?;
+    auth.require_scope(Scope::ChangeOwners, &krate.name)?;
+    let names = request.users.join(", ");
+    let auth = &auth;
+    let source = &source;
+    let krate = db
+        .transaction(|db| {
+            Box::pin(async move {
+                krate.lock_owners(db).await?;
+                let owners = lookup_owners(db, &request.users).await?;
+                for owner in &owners {
+                    let login = owner.login();
+                    info!("Removing {login} as an owner of {}", krate.name);
+                    owner.remove_from(db, &krate).await?;
+                    owner
+                        .audit_event(auth, source, AuditAction::RemoveOwner, &krate, &login)
+                        .record(db)
+                        .await?;
+                }
+                if !krate.has_owner_except(db, None).await? {
+                    return Err(CrateError::LastOwner(krate.name));
+                }
+                Ok(krate)
+            })
+        })
+        .await?;
+    Ok(Json(OwnersChangedResponse {
+        ok: true,
+        msg: format!("{names} removed as owners of {}", krate.name),
+    }))
+}
+
+async fn list_tokens(
+    mut db: Connection,
+    auth: Authentication,
+) -> Result<Json<TokensResponse>, TokenError> {
+    auth.require_unrestricted()?;
+    let tokens = auth.identity().tokens(&mut db).await?;
+    Ok(Json(TokensResponse {
+        tokens: tokens.iter().map(TokenInfo::from).collect(),
+    }))
+}
+
+async fn create_token(
+    mut db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    request: Result<Json<NewTokenRequest>, JsonRejection>,
+) -> Result<Json<NewTokenResponse>, TokenError> {
+    let Json(request) = request?;
+    auth.require_unrestricted()?;
+    let (scopes, expires_at) = request.restrictions()?;
+    if let Some(token) = auth.token() {
+        if !token.covers(&scopes, expires_at) {
+            return Err(TokenError::Escalation);
+        }
+    }
+    let auth = &auth;
+    let source = &source;
+    let (token, secret) = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let (token, secret) = auth
+                    .identity()
+                    .new_token(db, &request.title, &scopes, expires_at)
+                    .await?;
+                NewAuditEvent {
+                    subject: Some(auth.identity().id),
+                    detail: Some(&token.prefix),
+                    ..audit::event(auth, source, AuditAction::CreateToken)
+                }
+                .record(db)
+                .await?;
+                Ok::<_, DieselError>((token, secret))
+            })
+        })
+        .await?;
+    info!(
+        "{} created token {}... ({})",
+        auth.identity().name,
+        token.prefix,
+        token.title
+    );
+    Ok(Json(NewTokenResponse {
+        token: secret,
+        info: TokenInfo::from(&token),
+    }))
+}
+
+async fn delete_token(
+    mut db: Connection,
+    auth: Authentication,
+    source: SourceAddr,
+    request: Result<Json<DeleteTokenRequest>, JsonRejection>,
+) -> Result<Json<OkResponse>, TokenError> {
+    let Json(request) = request?;
+    auth.require_unrestricted()?;
+    let auth = &auth;
+    let source = &source;
+    let token = db
+        .transaction(|db| {
+            Box::pin(async move {
+                let token = auth
+                    .identity()
+                    .delete_token(db, request.id)
+                    .await?
+                    .ok_or_else(|| TokenError::UnknownToken(request.id.to_string()))?;
+                NewAuditEvent {
+                    subject: Some(auth.identity().id),
+                    detail: Some(&token.prefix),
+                    ..audit::event(auth, source, AuditAction::DeleteToken)
+                }
+                .record(db)
+                .await?;
+                Ok::<_, TokenError>(token)
+            })
+        })
+        .await?;
+    info!("{} deleted token {}...", auth.identity().name, token.prefix);
+    Ok(Json(OkResponse { ok: true }))
+}
+
+pub fn router(state: &AppState) -> Router<AppState> {
+    // Every other route authenticates itself, and must do so only once so
+    // that a signed token's nonce is not spent before the handler sees it
+    let reads = Router::new()
+        .route("/v1/crates", get(search_crates))
+        .route("/v1/crates/:name/owners", get(list_owners))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ));
+    Router::new()
+        .route("/v1/crates/new", put(publish_crate))
+        .route("/v1/crates/:name/:version/yank", delete(yank_crate))
+        .route("/v1/crates/:name/:version/unyank", put(unyank_crate))
+        .route(
+            "/v1/crates/:name/owners",
+            put(add_owners).delete(remove_owners),
+        )
+        .route(
+            "/v1/me/tokens",
+            get(list_tokens).post(create_token).delete(delete_token),
+        )
+        .merge(reads)
 }
@@ -0,0 +1,85 @@
+//! Recording changes made through the API in the audit log
+//!
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use database::models::{AuditAction, NewAuditEvent};
+
+use crate::{auth::Authentication, state::AppState};
+
+/// The address a request came from
+///
+/// When the connection comes from one of the configured trusted proxies,
+/// the address they were forwarding for (the last entry of
+/// `X-Forwarded-For` which is not itself a trusted proxy) is preferred to
+/// the address of the connection.  Anyone else could claim any address.
+pub struct SourceAddr(Option<String>);
+
+impl SourceAddr {
+    pub fn as_deref(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for SourceAddr {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let trusted = state.config().trusted_proxies();
+        let is_trusted = |addr: &str| {
+            addr.parse::<IpAddr>()
+                .is_ok_and(|addr| trusted.contains(&addr))
+        };
+        let Some(peer) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+        else {
+            return Ok(Self(None));
+        };
+        if !trusted.contains(&peer) {
+            return Ok(Self(Some(peer.to_string())));
+        }
+        let forwarded: Vec<_> = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let source = forwarded
+            .into_iter()
+            .rev()
+            .find(|addr| !addr.is_empty() && !is_trusted(addr))
+            .map(String::from)
+            .unwrap_or_else(|| peer.to_string());
+        Ok(Self(Some(source)))
+    }
+}
+
+/// An audit event for an action taken by an authenticated request
+pub fn event<'a>(
+    auth: &Authentication,
+    source: &'a SourceAddr,
+    action: AuditAction,
+) -> NewAuditEvent<'a> {
+    NewAuditEvent {
+        actor: Some(auth.identity().id),
+        token: auth.token().map(|token| token.id),
+        source: source.as_deref(),
+        ..NewAuditEvent::new(action)
+    }
+}
@@ -3,21 +3,82 @@
 
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{header::AUTHORIZATION, request::Parts, StatusCode},
-    response::{IntoResponse, Response},
+    extract::{FromRequestParts, State},
+    http::{header::AUTHORIZATION, request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::Response,
 };
 use database::{
-    models::{Identity, Token},
-    Connection,
+    models::{self, Identity, Scope, Token, UsedNonce},
+    Connection, DieselError,
 };
+use thiserror::Error;
 use tracing::info;
 
-use crate::state::AppState;
+use crate::{
+    error::ApiError,
+    paseto::{Claims, PublicKey, UntrustedToken},
+    state::AppState,
+};
+
+#[derive(Debug, Error)]
+#[error("This token is not permitted to {scope} the crate {krate}")]
+pub struct MissingScope {
+    scope: Scope,
+    krate: String,
+}
+
+#[derive(Debug, Error)]
+#[error("The signed token was not issued for this request: {0}")]
+pub struct ClaimMismatch(String);
+
+#[derive(Debug, Error)]
+#[error("This needs a token without scopes, signed tokens only cover the request they describe")]
+pub struct Restricted;
+
+/// The registry operation a request performs, which a signed token must
+/// describe exactly
+pub enum Mutation<'a> {
+    Publish {
+        name: &'a str,
+        vers: &'a str,
+        cksum: &'a str,
+    },
+    Yank {
+        name: &'a str,
+        vers: &'a str,
+    },
+    Unyank {
+        name: &'a str,
+        vers: &'a str,
+    },
+    Owners {
+        name: &'a str,
+    },
+}
+
+impl Mutation<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Mutation::Publish { .. } => "publish",
+            Mutation::Yank { .. } => "yank",
+            Mutation::Unyank { .. } => "unyank",
+            Mutation::Owners { .. } => "owners",
+        }
+    }
+}
+
+/// How the request was authenticated
+pub enum Credential {
+    /// A bearer token issued by the registry
+    Token(Token),
+    /// A token signed by one of the identity's keys
+    Paseto(Claims),
+}
 
 pub struct Authentication {
     identity: Identity,
-    token: Token,
+    credential: Credential,
 }
 
 impl Authentication {
@@ -25,51 +86,252 @@ impl Authentication {
         &self.identity
     }
 
-    #[allow(unused)]
-    pub fn token(&self) -> &Token {
-        &self.token
+    /// The bearer token used, if the request was not signed
+    pub fn token(&self) -> Option<&Token> {
+        match &self.credential {
+            Credential::Token(token) => Some(token),
+            Credential::Paseto(_) => None,
+        }
+    }
+
+    /// Check that the token used permits the given action on the named crate
+    ///
+    /// Signed tokens carry no scopes, they are limited by their claims instead
+    pub fn require_scope(&self, scope: Scope, krate: &str) -> Result<(), MissingScope> {
+        match &self.credential {
+            Credential::Token(token) if !token.permits(scope, krate) => Err(MissingScope {
+                scope,
+                krate: krate.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that a signed token was issued for exactly this mutation
+    ///
+    /// Bearer tokens say nothing about the request, so always pass
+    pub fn require_mutation(&self, mutation: Mutation<'_>) -> Result<(), ClaimMismatch> {
+        let Credential::Paseto(claims) = &self.credential else {
+            return Ok(());
+        };
+        let check = |claim: &str, have: &Option<String>, want: &str| match have {
+            Some(have) if have == want => Ok(()),
+            Some(have) => Err(ClaimMismatch(format!(
+                "{claim} is {have} but the request is for {want}"
+            ))),
+            None => Err(ClaimMismatch(format!("{claim} is missing"))),
+        };
+        check("mutation", &claims.mutation, mutation.kind())?;
+        match mutation {
+            Mutation::Publish { name, vers, cksum } => {
+                check("name", &claims.name, name)?;
+                check("vers", &claims.vers, vers)?;
+                check("cksum", &claims.cksum, cksum)
+            }
+            Mutation::Yank { name, vers } | Mutation::Unyank { name, vers } => {
+                check("name", &claims.name, name)?;
+                check("vers", &claims.vers, vers)
+            }
+            Mutation::Owners { name } => check("name", &claims.name, name),
+        }
     }
 
-    async fn from_token(db: &mut Connection, token: &str) -> Option<Self> {
-        let token = Token::from_token(db, token).await.ok()??;
-        let identity = token.owner(db).await.ok()?;
-        Some(Self { identity, token })
+    /// Check that the credential may act for its identity in everything
+    ///
+    /// Only a bearer token without scopes can.  A signed token is bound to
+    /// the one registry operation its claims describe, and there is no claim
+    /// for anything else.
+    pub fn require_unrestricted(&self) -> Result<(), Restricted> {
+        match &self.credential {
+            Credential::Token(token)
+                if token.endpoint_scopes.is_none() && token.crate_scopes.is_none() =>
+            {
+                Ok(())
+            }
+            _ => Err(Restricted),
+        }
+    }
+
+    async fn from_token(db: &mut Connection, token: &str) -> Result<Self, ApiError> {
+        let db_error =
+            |e: DieselError| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        let mut token = Token::from_token(db, token)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Invalid or unknown token"))?;
+        if token.is_expired() {
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                format!("The token {}... has expired", token.prefix),
+            ));
+        }
+        let identity = token.owner(db).await.map_err(db_error)?;
+        check_enabled(&identity)?;
+        token.touch(db).await.map_err(db_error)?;
+        Ok(Self {
+            identity,
+            credential: Credential::Token(token),
+        })
+    }
+
+    async fn from_paseto(
+        db: &mut Connection,
+        state: &AppState,
+        token: &str,
+    ) -> Result<Self, ApiError> {
+        let unauthorised = |detail: String| ApiError::new(StatusCode::UNAUTHORIZED, detail);
+        let db_error =
+            |e: DieselError| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        let untrusted = UntrustedToken::parse(token).map_err(|e| unauthorised(e.to_string()))?;
+        let key = models::PublicKey::by_key_id(db, &untrusted.footer().kip)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| {
+                unauthorised(format!("Unknown signing key {}", untrusted.footer().kip))
+            })?;
+        let verifier = PublicKey::from_paserk(&key.paserk).map_err(|e| {
+            ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Stored key {} is unusable: {e}", key.key_id),
+            )
+        })?;
+        let nonce = untrusted.nonce();
+        let (footer, claims) = untrusted
+            .verify(&verifier)
+            .map_err(|e| unauthorised(e.to_string()))?;
+
+        if claims.v.is_some_and(|v| v != 1) {
+            return Err(unauthorised("Unsupported token claims version".into()));
+        }
+        let config = state.config();
+        if footer.url.trim_end_matches('/') != config.index_url().trim_end_matches('/') {
+            return Err(unauthorised(format!(
+                "Token was issued for the registry {}",
+                footer.url
+            )));
+        }
+        let window = config.paseto_window();
+        let issued_at = claims
+            .issued_within(window)
+            .map_err(|e| unauthorised(e.to_string()))?;
+        // A challenge could only be honoured if this registry had issued it,
+        // which it never does
+        if claims.challenge.is_some() {
+            return Err(unauthorised(
+                "Token answers a challenge this registry did not issue".into(),
+            ));
+        }
+        // Mutations must not be replayed, and since the token is only
+        // acceptable within the window it need only be remembered that long
+        if claims.mutation.is_some() {
+            let fresh = UsedNonce {
+                nonce: &nonce,
+                expires_at: issued_at + window,
+            }
+            .claim(db)
+            .await
+            .map_err(db_error)?;
+            if !fresh {
+                return Err(unauthorised("Token has already been used".into()));
+            }
+        }
+
+        let identity = key.owner(db).await.map_err(db_error)?;
+        check_enabled(&identity)?;
+        Ok(Self {
+            identity,
+            credential: Credential::Paseto(claims),
+        })
+    }
+}
+
+fn check_enabled(identity: &Identity) -> Result<(), ApiError> {
+    if identity.disabled {
+        Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("The user {} is disabled", identity.name),
+        ))
+    } else {
+        Ok(())
     }
 }
 
 #[async_trait]
 impl FromRequestParts<AppState> for Authentication {
-    type Rejection = Response;
+    type Rejection = ApiError;
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
         info!("Begun looking for authentication data...");
-        let mut db = Connection::from_request_parts(parts, state)
-            .await
-            .map_err(|e| e.into_response())?;
+        let mut db = Connection::from_request_parts(parts, state).await?;
 
-        // Authorisation should be a token presented as a bearer token
+        // Authorisation should be a token presented as a bearer token, or
+        // a token signed by one of the user's keys
         let auth_header = parts.headers.get(AUTHORIZATION).ok_or_else(|| {
-            (StatusCode::FORBIDDEN, "You need to provide a token, sorry").into_response()
+            ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "You need to provide a token, sorry",
+            )
         })?;
         let token = auth_header.to_str().map_err(|e| {
-            (
+            ApiError::new(
                 StatusCode::BAD_REQUEST,
                 format!("Invalid authorisation header: {e}"),
             )
-                .into_response()
         })?;
 
-        Authentication::from_token(&mut db, token)
-            .await
-            .ok_or_else(|| {
-                (
-                    StatusCode::BAD_REQUEST,
-                    format!("Invalid or unknown token: {token}"),
-                )
-                    .into_response()
-            })
+        if token.starts_with("v3.public.") {
+            Authentication::from_paseto(&mut db, state, token).await
+        } else {
+            Authentication::from_token(&mut db, token).await
+        }
+    }
+}
+
+/// Middleware which rejects requests without a valid token when the
+/// registry is configured to require authentication for everything
+pub async fn require_auth<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    if !state.config().auth_required() {
+        return Ok(next.run(request).await);
+    }
+    let (mut parts, body) = request.into_parts();
+    Authentication::from_request_parts(&mut parts, &state).await?;
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+/// Authentication by an admin, using a bearer token which is not restricted
+/// to particular endpoints or crates
+pub struct Admin(pub Authentication);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth = Authentication::from_request_parts(parts, state).await?;
+        if !auth.identity().admin {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Only admins may use the admin API",
+            ));
+        }
+        // Signed tokens have no claim which could describe an admin
+        // operation, so cannot be bound to one
+        auth.require_unrestricted().map_err(|_| {
+            ApiError::new(
+                StatusCode::FORBIDDEN,
+                "The admin API needs a bearer token without scopes",
+            )
+        })?;
+        Ok(Self(auth))
     }
 }
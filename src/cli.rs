@@ -1,7 +1,15 @@
 use clap::{
     builder::{styling::AnsiColor, Styles},
-    Parser,
+    Parser, ValueEnum,
 };
+use database::models::{AuditAction, Scope, TEAM_PREFIX};
+use metadata::name::check_pattern;
+use time::{
+    format_description::well_known::Rfc3339, macros::format_description, Date, Duration,
+    OffsetDateTime,
+};
+
+use crate::paseto::PublicKey;
 
 const CLI_STYLE: Styles = Styles::styled()
     .header(AnsiColor::Yellow.on_default())
@@ -23,6 +31,145 @@ pub enum Cmd {
     #[default]
     Serve,
     User(User),
+    Group(Group),
+    Crate(Crate),
+    Audit(Audit),
+}
+
+/// Inspect and administer crates
+#[derive(Debug, Parser)]
+pub struct Crate {
+    #[clap(subcommand)]
+    pub command: CrateCmd,
+}
+
+#[derive(Debug, Default, Parser)]
+pub enum CrateCmd {
+    #[default]
+    List,
+    Versions {
+        name: String,
+    },
+    Yank {
+        name: String,
+        version: String,
+    },
+    Unyank {
+        name: String,
+        version: String,
+    },
+    /// Replace every owner of a crate with the given user, or `team:` team
+    Transfer {
+        name: String,
+        owner: String,
+    },
+    /// Print the index entry stored for a version
+    Show {
+        name: String,
+        version: String,
+    },
+    /// List every version awaiting approval
+    Pending,
+    /// Approve a version, exposing it in the index
+    Approve {
+        name: String,
+        version: String,
+    },
+    /// Set whether new versions of a crate must be approved
+    Approval {
+        name: String,
+        #[clap(value_enum)]
+        policy: ApprovalPolicy,
+    },
+    /// Hide a version from the index until the given time (YYYY-MM-DD or RFC 3339)
+    Embargo {
+        name: String,
+        version: String,
+        #[clap(value_parser = parse_time)]
+        until: OffsetDateTime,
+    },
+    /// Make an embargoed version visible immediately
+    LiftEmbargo {
+        name: String,
+        version: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ApprovalPolicy {
+    /// New versions must be approved
+    Required,
+    /// New versions are exposed immediately
+    Optional,
+    /// Follow the registry's REQUIRE_APPROVAL setting
+    Default,
+}
+
+impl ApprovalPolicy {
+    /// The value stored on the crate for this policy
+    pub fn requires_approval(self) -> Option<bool> {
+        match self {
+            ApprovalPolicy::Required => Some(true),
+            ApprovalPolicy::Optional => Some(false),
+            ApprovalPolicy::Default => None,
+        }
+    }
+}
+
+/// Manage teams of users, which may own crates as a whole.  A team is
+/// named as an owner with the prefix `team:`, e.g. `team:platform`.
+#[derive(Debug, Parser)]
+pub struct Group {
+    #[clap(subcommand)]
+    pub command: GroupCmd,
+}
+
+#[derive(Debug, Default, Parser)]
+pub enum GroupCmd {
+    #[default]
+    List,
+    Create {
+        #[clap(value_parser = parse_team_name)]
+        name: String,
+    },
+    /// Delete a team, which must not own any crates
+    Delete {
+        name: String,
+    },
+    Members {
+        name: String,
+    },
+    AddMember {
+        name: String,
+        user: String,
+    },
+    RemoveMember {
+        name: String,
+        user: String,
+    },
+}
+
+/// Show the audit log of changes made to the registry
+#[derive(Debug, Parser)]
+pub struct Audit {
+    /// Only show events concerning this crate
+    #[clap(long = "crate")]
+    pub krate: Option<String>,
+    /// Only show events made by, or affecting, this user
+    #[clap(long = "user")]
+    pub user: Option<String>,
+    /// Only show events of this kind, e.g. publish, yank or create-token
+    #[clap(long = "action")]
+    pub action: Option<AuditAction>,
+    /// Only show events at or after this time (YYYY-MM-DD or RFC 3339)
+    #[clap(long = "since", value_parser = parse_time)]
+    pub since: Option<OffsetDateTime>,
+    /// Only show events before this time (YYYY-MM-DD or RFC 3339)
+    #[clap(long = "until", value_parser = parse_time)]
+    pub until: Option<OffsetDateTime>,
+    /// Only show this many of the most recent matching events
+    #[clap(long = "limit")]
+    pub limit: Option<i64>,
 }
 
 #[derive(Debug, Parser)]
@@ -36,19 +183,139 @@ pub enum UserCmd {
     #[default]
     List,
     Create {
+        #[clap(value_parser = parse_user_name)]
         name: String,
         #[clap(long = "admin")]
         admin: bool,
     },
+    /// Delete a user, refusing if that would leave any crate without owners
+    Delete {
+        name: String,
+        /// Make this user an owner of every crate the deleted user owned
+        #[clap(long = "transfer-to")]
+        transfer_to: Option<String>,
+    },
+    Rename {
+        name: String,
+        #[clap(value_parser = parse_user_name)]
+        new_name: String,
+    },
+    SetAdmin {
+        name: String,
+    },
+    UnsetAdmin {
+        name: String,
+    },
+    /// Stop a user's tokens and keys from authenticating, keeping their history
+    Disable {
+        name: String,
+    },
+    Enable {
+        name: String,
+    },
     Tokens {
         name: String,
     },
     NewToken {
         name: String,
         title: String,
+        /// Restrict the token to these endpoints (publish-new, publish-update,
+        /// yank, change-owners).  If not given, all endpoints are permitted.
+        #[clap(long = "scope")]
+        scopes: Vec<Scope>,
+        /// Restrict the token to crates matching these patterns, a trailing
+        /// `*` matches any suffix.  If not given, all crates are permitted.
+        #[clap(long = "crate", value_parser = parse_crate_pattern)]
+        crates: Vec<String>,
+        /// Expire the token after this long, e.g. 90d, 12h, 2w or 30m
+        #[clap(long = "expires-in", value_parser = parse_duration)]
+        expires_in: Option<Duration>,
     },
     DeleteToken {
         name: String,
-        token: String,
+        /// The token id, as shown by the tokens command
+        token: i32,
+    },
+    Keys {
+        name: String,
     },
+    /// Register a public key for use with cargo's `cargo:paseto` provider
+    AddKey {
+        name: String,
+        title: String,
+        /// The public key, as printed by `cargo login` (k3.public....)
+        #[clap(value_parser = parse_paserk)]
+        paserk: PublicKey,
+    },
+    DeleteKey {
+        name: String,
+        /// The key ID, as shown by the keys command
+        key_id: String,
+    },
+}
+
+fn parse_user_name(name: &str) -> Result<String, String> {
+    if name.is_empty() {
+        Err("User names may not be empty".to_string())
+    } else if name.starts_with(TEAM_PREFIX) {
+        Err(format!("User names may not start with {TEAM_PREFIX}"))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+fn parse_paserk(paserk: &str) -> Result<PublicKey, String> {
+    PublicKey::from_paserk(paserk).map_err(|e| e.to_string())
+}
+
+fn parse_crate_pattern(pattern: &str) -> Result<String, String> {
+    check_pattern(pattern)
+        .map(|_| pattern.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("Missing unit in {duration}, expected one of m, h, d or w"))?;
+    let (count, unit) = duration.split_at(split);
+    let count: i64 = count
+        .parse()
+        .map_err(|e| format!("Bad number in {duration}: {e}"))?;
+    let seconds = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit {unit}, expected one of m, h, d or w")),
+    };
+    // The duration is added to the current time, so must keep that in range
+    count
+        .checked_mul(seconds)
+        .map(Duration::seconds)
+        .filter(|&duration| OffsetDateTime::now_utc().checked_add(duration).is_some())
+        .ok_or_else(|| format!("Duration {duration} is too long"))
+}
+
+fn parse_team_name(name: &str) -> Result<String, String> {
+    if name.is_empty() {
+        Err("Team names may not be empty".to_string())
+    } else if let Some(ch) = name
+        .chars()
+        .find(|&ch| !(ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'))
+    {
+        Err(format!(
+            "Team name {name} contains invalid character {ch:?}, only ASCII alphanumerics, '-' and '_' are allowed"
+        ))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+fn parse_time(when: &str) -> Result<OffsetDateTime, String> {
+    if let Ok(date) = Date::parse(when, format_description!("[year]-[month]-[day]")) {
+        return Ok(date.midnight().assume_utc());
+    }
+    OffsetDateTime::parse(when, &Rfc3339)
+        .map_err(|e| format!("Bad time {when}, expected YYYY-MM-DD or RFC 3339: {e}"))
 }
@@ -1,4 +1,5 @@
 use std::{
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -6,6 +7,7 @@ use std::{
 use config::{Config, ConfigError, Environment};
 use git_testament::git_testament;
 use serde::Deserialize;
+use time::Duration;
 use url::Url;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -17,12 +19,40 @@ pub struct ConfigurationInner {
     version: String,
     base_url: Url,
     crate_path: PathBuf,
+    #[serde(default)]
+    upload_path: PathBuf,
+    #[serde(default)]
+    auth_required: bool,
+    #[serde(default = "default_paseto_window")]
+    paseto_window: u64,
+    #[serde(default)]
+    require_approval: bool,
+    #[serde(default)]
+    index_max_age: u64,
+    #[serde(default = "default_index_cache_size")]
+    index_cache_size: usize,
+    #[serde(default = "default_index_cache_ttl")]
+    index_cache_ttl: u64,
+    #[serde(default)]
+    trusted_proxies: Vec<IpAddr>,
 }
 
 fn default_port() -> u16 {
     1537
 }
 
+fn default_index_cache_size() -> usize {
+    1024
+}
+
+fn default_index_cache_ttl() -> u64 {
+    300
+}
+
+fn default_paseto_window() -> u64 {
+    60
+}
+
 git_testament!(VERSION);
 
 #[derive(Clone)]
@@ -63,16 +93,90 @@ impl ConfigurationInner {
     pub fn crate_path(&self) -> &Path {
         &self.crate_path
     }
+
+    /// Where uploads are staged until they are published, which must be on
+    /// the same filesystem as the crates path but not inside it
+    pub fn upload_path(&self) -> &Path {
+        &self.upload_path
+    }
+
+    /// Whether a token is needed to read the index and download crates
+    pub fn auth_required(&self) -> bool {
+        self.auth_required
+    }
+
+    /// How far from now an asymmetric token's issue time may be
+    pub fn paseto_window(&self) -> Duration {
+        Duration::seconds(self.paseto_window as i64)
+    }
+
+    /// Whether new versions must be approved by an admin before they are
+    /// exposed in the index, unless the crate says otherwise
+    pub fn require_approval(&self) -> bool {
+        self.require_approval
+    }
+
+    /// How long, in seconds, clients may use an index file before checking
+    /// whether it has changed
+    pub fn index_max_age(&self) -> u64 {
+        self.index_max_age
+    }
+
+    /// How many crates' index files to keep in memory, 0 disables the cache
+    pub fn index_cache_size(&self) -> usize {
+        self.index_cache_size
+    }
+
+    /// How long a cached index file may be served for, even if no change
+    /// to the crate has been announced
+    pub fn index_cache_ttl(&self) -> Duration {
+        Duration::seconds(self.index_cache_ttl as i64)
+    }
+
+    /// Reverse proxies whose `X-Forwarded-For` headers may be believed
+    pub fn trusted_proxies(&self) -> &[IpAddr] {
+        &self.trusted_proxies
+    }
+
+    /// The index URL cargo knows this registry by
+    pub fn index_url(&self) -> String {
+        format!(
+            "sparse+{}/crates/",
+            self.base_url.as_str().trim_end_matches('/')
+        )
+    }
 }
 
 impl Configuration {
     /// Load a configuration from the environment
     pub fn load() -> Result<Configuration, ConfigError> {
-        let config = Config::builder().add_source(Environment::default().try_parsing(true));
+        let config = Config::builder().add_source(
+            Environment::default()
+                .try_parsing(true)
+                .list_separator(",")
+                .with_list_parse_key("trusted_proxies"),
+        );
         let mut inner: ConfigurationInner = config.build()?.try_deserialize()?;
         inner.version = format!("{VERSION}");
         inner.crate_path = std::fs::canonicalize(inner.crate_path)
             .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+        if inner.upload_path.as_os_str().is_empty() {
+            // Default to a hidden sibling of the crates path
+            let name = inner.crate_path.file_name().unwrap_or_default();
+            let mut hidden = std::ffi::OsString::from(".");
+            hidden.push(name);
+            hidden.push("-uploads");
+            inner.upload_path = inner.crate_path.with_file_name(hidden);
+        }
+        std::fs::create_dir_all(&inner.upload_path)
+            .and_then(|_| std::fs::canonicalize(&inner.upload_path))
+            .map(|path| inner.upload_path = path)
+            .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+        if inner.upload_path.starts_with(&inner.crate_path) {
+            return Err(ConfigError::Message(
+                "The upload path must not be inside the crate path".into(),
+            ));
+        }
         Ok(Self {
             inner: Arc::new(inner),
         })
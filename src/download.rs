@@ -0,0 +1,78 @@
+//! Serving crate files to cargo
+//!
+
+use axum::{
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use database::{models::Krate, Connection, DieselError};
+use metadata::name::CrateName;
+use percent_encoding::percent_decode_str;
+use tower_http::services::ServeDir;
+
+use crate::{auth, error::ApiError, state::AppState};
+
+/// Split a crate filename, `{crate}-{version}.crate`, into its parts
+///
+/// Crate names may contain hyphens but never dots, while versions always
+/// contain dots, so the version starts after the last hyphen before the
+/// first dot.
+fn split_filename(filename: &str) -> Option<(&str, &str)> {
+    let stem = filename.strip_suffix(".crate")?;
+    let dot = stem.find('.')?;
+    let hyphen = stem[..dot].rfind('-')?;
+    Some((&stem[..hyphen], &stem[hyphen + 1..]))
+}
+
+/// Middleware which refuses to serve versions which are not yet visible in
+/// the index, either because they await approval or are under embargo
+///
+/// The path is checked as `ServeDir` will see it, after percent-decoding,
+/// and anything which is not a crate file is refused outright.
+async fn hide_invisible<B>(
+    mut db: Connection,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let db_error = |e: DieselError| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    // Answer exactly as though the file did not exist, so as not to give
+    // away that the version is coming
+    let not_found = || Ok(StatusCode::NOT_FOUND.into_response());
+    let Ok(path) = percent_decode_str(request.uri().path()).decode_utf8() else {
+        return not_found();
+    };
+    let parts = path
+        .rsplit('/')
+        .next()
+        .and_then(split_filename)
+        .and_then(|(name, vers)| Some((CrateName::new(name).ok()?, vers.to_string())));
+    let Some((name, vers)) = parts else {
+        return not_found();
+    };
+    if let Some(krate) = Krate::by_name_ignoring_case(&mut db, &name)
+        .await
+        .map_err(db_error)?
+    {
+        let version = krate.version(&mut db, &vers).await.map_err(db_error)?;
+        if version.is_some_and(|version| !version.is_visible()) {
+            return not_found();
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+pub fn router(state: &AppState) -> Router<AppState> {
+    let dlserver = ServeDir::new(state.config().crate_path());
+    Router::new()
+        .nest_service("/download", dlserver)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            hide_invisible,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ))
+}
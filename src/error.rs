@@ -0,0 +1,60 @@
+//! Errors reported to cargo
+//!
+//! Cargo only shows the detail of a registry error to the user if the
+//! response body has the shape `{"errors": [{"detail": "..."}]}`, so every
+//! handler's error type converts into [`ApiError`] to produce that.
+
+use axum::{
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use database::axum_link::ConnectionRejection;
+use serde::Serialize;
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    detail: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorList {
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+    detail: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorList {
+            errors: vec![ErrorDetail {
+                detail: self.detail,
+            }],
+        };
+        if self.status == StatusCode::UNAUTHORIZED {
+            // Cargo needs this to know it should retry with a token
+            (self.status, [(WWW_AUTHENTICATE, "Cargo")], Json(body)).into_response()
+        } else {
+            (self.status, Json(body)).into_response()
+        }
+    }
+}
+
+impl From<ConnectionRejection> for ApiError {
+    fn from(value: ConnectionRejection) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, value.to_string())
+    }
+}
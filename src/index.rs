@@ -1,25 +1,43 @@
 //! The crate index and such
 
+use std::sync::Arc;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{FromRef, Path, State},
+    http::{
+        header::{
+            CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        },
+        HeaderMap, StatusCode,
+    },
+    middleware,
+    response::{IntoResponse, Response},
     routing::get,
     Json, Router,
 };
+use bytes::Bytes;
 use database::{
+    axum_link::ConnectionRejection,
     models::{Krate, KrateVer},
     Connection,
 };
+use metadata::name::CrateName;
 use serde::Serialize;
 use thiserror::Error;
+use time::{
+    format_description::FormatItem, macros::format_description, OffsetDateTime, PrimitiveDateTime,
+    UtcOffset,
+};
+use tracing::{info, warn};
 
-use crate::{auth::Authentication, configuration::Configuration, state::AppState};
+use crate::{auth, configuration::Configuration, error::ApiError, state::AppState};
 
 #[derive(Serialize)]
 struct ConfigJson {
     dl: String,
     api: String,
+    #[serde(rename = "auth-required", skip_serializing_if = "std::ops::Not::not")]
+    auth_required: bool,
 }
 
 async fn config_json(State(config): State<Configuration>) -> Json<ConfigJson> {
@@ -42,54 +60,259 @@ async fn config_json(State(config): State<Configuration>) -> Json<ConfigJson> {
     Json(ConfigJson {
         api: base_url,
         dl: dl_url,
+        auth_required: config.auth_required(),
     })
 }
 
 #[derive(Debug, Error)]
 enum KrateIndexError {
-    #[error("Bad crate name: {0}")]
-    BadCrateName(String),
+    #[error("No index file at {0}")]
+    BadPath(String),
     #[error("Unknown crate name: {0}")]
     UnknownCrate(String),
     #[error("Database error: {0}")]
     Database(#[from] database::DieselError),
+    #[error(transparent)]
+    Connection(#[from] ConnectionRejection),
 }
 
-impl IntoResponse for KrateIndexError {
-    fn into_response(self) -> axum::response::Response {
-        let code = match &self {
-            Self::BadCrateName(_) => StatusCode::BAD_REQUEST,
-            Self::UnknownCrate(_) => StatusCode::NOT_FOUND,
-            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+impl From<KrateIndexError> for ApiError {
+    fn from(value: KrateIndexError) -> Self {
+        let code = match &value {
+            KrateIndexError::BadPath(_) => StatusCode::NOT_FOUND,
+            KrateIndexError::UnknownCrate(_) => StatusCode::NOT_FOUND,
+            KrateIndexError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            KrateIndexError::Connection(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
-        (code, self.to_string()).into_response()
+        ApiError::new(code, value.to_string())
     }
 }
 
-async fn krate_index(
-    mut db: Connection,
-    _auth: Option<Authentication>,
-    Path(krate): Path<String>,
-) -> Result<String, KrateIndexError> {
-    let slashpos = krate
-        .rfind('/')
-        .ok_or_else(|| KrateIndexError::BadCrateName(krate.clone()))?;
-    let krate_name = &krate[slashpos + 1..];
-    if krate_name.is_empty() {
-        return Err(KrateIndexError::BadCrateName(krate));
+impl IntoResponse for KrateIndexError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+/// The IMF-fixdate form of HTTP dates, the only one we ever send
+const HTTP_DATE: &[FormatItem<'static>] = format_description!(
+    "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+);
+
+/// Whether an `If-None-Match` header matches the given entity tag
+///
+/// This uses the weak comparison, as RFC 9110 requires for `If-None-Match`
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether the client's copy of an index file is still current
+fn not_modified(headers: &HeaderMap, etag: &str, modified: OffsetDateTime) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    // If-Modified-Since is only considered when there is no If-None-Match
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        return etag_matches(if_none_match, etag);
     }
-    let dbkrate = Krate::by_name(&mut db, krate_name)
-        .await?
-        .ok_or_else(|| KrateIndexError::UnknownCrate(krate.clone()))?;
-    let versions = dbkrate.versions(&mut db).await?;
+    header(IF_MODIFIED_SINCE)
+        .and_then(|since| PrimitiveDateTime::parse(since, HTTP_DATE).ok())
+        .map(PrimitiveDateTime::assume_utc)
+        .is_some_and(|since| modified.replace_nanosecond(0).unwrap_or(modified) <= since)
+}
+
+/// A crate's index file, rendered along with its validators
+pub struct IndexFile {
+    body: Bytes,
+    etag: String,
+    modified: OffsetDateTime,
+}
+
+/// Render the index file of a crate, and say until when it is good
+///
+/// Versions awaiting approval or under embargo are not yet part of the
+/// index, and a crate with none visible is not known at all.
+async fn render_index(
+    db: &mut Connection,
+    krate_name: &CrateName,
+) -> Result<Option<(IndexFile, Option<OffsetDateTime>)>, KrateIndexError> {
+    // Cargo always requests the lowercased name, but folds nothing else
+    let Some(dbkrate) = Krate::by_name_ignoring_case(db, krate_name).await? else {
+        return Ok(None);
+    };
+    let versions = dbkrate.visible_versions(db).await?;
+    if versions.is_empty() {
+        return Ok(None);
+    }
+    let modified = dbkrate.index_modified(&versions);
+    let until = dbkrate.next_embargo(db).await?;
 
     let versions: Vec<String> = versions.iter().map(KrateVer::index_line).collect();
+    let body = versions.join("\n");
+    let etag = format!("\"{}\"", sha256::digest(body.as_bytes()));
+    let file = IndexFile {
+        body: body.into(),
+        etag,
+        modified,
+    };
+    Ok(Some((file, until)))
+}
+
+/// The crate an index path is for, if it is exactly the path cargo would
+/// request, `{prefix}/{name}` with both parts lowercased
+///
+/// Anything else is refused, so that each index file has a single URL.
+fn parse_index_path(path: &str) -> Option<CrateName> {
+    let (_, name) = path.rsplit_once('/')?;
+    let lower = CrateName::new(&name.to_ascii_lowercase()).ok()?;
+    (path == format!("{}/{lower}", lower.prefix())).then_some(lower)
+}
+
+async fn krate_index(
+    State(state): State<AppState>,
+    Path(krate): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, KrateIndexError> {
+    let krate_name =
+        parse_index_path(&krate).ok_or_else(|| KrateIndexError::BadPath(krate.clone()))?;
+    let key = krate_name.to_string();
+    let cache = state.index_cache();
+    let file = match cache.get(&key) {
+        Some(file) => file,
+        None => {
+            let generation = cache.generation();
+            let mut db = Connection::acquire(&database::Pool::from_ref(&state)).await?;
+            let (file, until) = render_index(&mut db, &krate_name)
+                .await?
+                .ok_or_else(|| KrateIndexError::UnknownCrate(krate.clone()))?;
+            let file = Arc::new(file);
+            cache.insert(key, Arc::clone(&file), generation, until);
+            file
+        }
+    };
+
+    let config = state.config();
+    let cache_control = format!(
+        "{}, max-age={}",
+        if config.auth_required() {
+            "private"
+        } else {
+            "public"
+        },
+        config.index_max_age()
+    );
+    let last_modified = file
+        .modified
+        .to_offset(UtcOffset::UTC)
+        .format(HTTP_DATE)
+        .unwrap_or_default();
+    let validators = [
+        (ETAG, file.etag.clone()),
+        (LAST_MODIFIED, last_modified),
+        (CACHE_CONTROL, cache_control),
+    ];
+    if not_modified(&headers, &file.etag, file.modified) {
+        Ok((StatusCode::NOT_MODIFIED, validators).into_response())
+    } else {
+        Ok((
+            validators,
+            [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+            file.body.clone(),
+        )
+            .into_response())
+    }
+}
 
-    Ok(versions.join("\n"))
+/// Keep the index cache coherent with the database, by dropping the files
+/// of crates which other requests or servers announce have changed
+pub async fn invalidate_on_notify(state: AppState) {
+    let db_url = state.config().database_url().to_string();
+    let cache = state.index_cache();
+    loop {
+        match database::listen(&db_url, database::INDEX_CHANNEL).await {
+            Ok(mut notifications) => {
+                // Anything may have changed while we were not listening
+                cache.clear();
+                info!("Listening for index changes");
+                while let Some(notification) = notifications.next().await {
+                    match notification {
+                        Ok(name) => cache.invalidate(&name),
+                        Err(e) => {
+                            warn!("Lost index change notifications: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!("Unable to listen for index changes: {e}"),
+        }
+        cache.clear();
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    }
 }
 
-pub fn router(_state: &AppState) -> Router<AppState> {
+pub fn router(state: &AppState) -> Router<AppState> {
     Router::new()
         .route("/config.json", get(config_json))
         .route("/*krate", get(krate_index))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &str) -> Option<String> {
+        parse_index_path(path).map(|name| name.to_string())
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(parse("1/a").as_deref(), Some("a"));
+        assert_eq!(parse("2/ab").as_deref(), Some("ab"));
+        assert_eq!(parse("3/a/abc").as_deref(), Some("abc"));
+        assert_eq!(parse("ab/cd/abcd").as_deref(), Some("abcd"));
+        assert_eq!(parse("se/rd/serde").as_deref(), Some("serde"));
+        assert_eq!(parse("fo/o-/foo-bar").as_deref(), Some("foo-bar"));
+    }
+
+    #[test]
+    fn wrong_prefix() {
+        for path in [
+            "zz/zz/serde",
+            "3/b/abc",
+            "2/a",
+            "1/ab",
+            "se/serde",
+            "se/rd/se/rd/serde",
+            "serde",
+            "se/rd/",
+        ] {
+            assert_eq!(parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn uppercase() {
+        for path in [
+            "Se/rd/Serde",
+            "se/rd/Serde",
+            "SE/RD/serde",
+            "3/A/abc",
+            "3/a/ABC",
+        ] {
+            assert_eq!(parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        for path in ["3/s/std", "2/1a", "se/rd/serde.json", "..", "1/_"] {
+            assert_eq!(parse(path), None, "{path}");
+        }
+    }
 }
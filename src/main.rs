@@ -1,23 +1,36 @@
-use std::{io::IsTerminal, net::SocketAddr};
+use std::{collections::HashMap, io::IsTerminal, net::SocketAddr};
 
 use axum::{extract::DefaultBodyLimit, Router};
 use clap::Parser;
-use database::{apply_migrations, create_pool, AsyncPgConnection, Pool};
+use database::{
+    apply_migrations, create_pool,
+    models::{
+        AuditAction, AuditEvent, AuditFilter, Identity, Krate, KrateVer, NewAuditEvent, Team,
+        TokenScopes, TEAM_PREFIX,
+    },
+    AsyncConnection, AsyncPgConnection, DieselError, Pool,
+};
+use time::OffsetDateTime;
 use tower_http::{
-    services::ServeDir,
     trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
     LatencyUnit,
 };
 use tracing::{info, warn, Level};
 use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
 
+mod admin;
 mod api;
+mod audit;
 mod auth;
 mod cli;
 mod configuration;
+mod download;
+mod error;
 mod index;
+mod paseto;
 mod state;
 
+use api::format_time;
 use cli::Cli;
 use configuration::Configuration;
 use state::AppState;
@@ -64,17 +77,21 @@ async fn main() {
     match cli.command {
         None | Some(cli::Cmd::Serve) => serve(config, pool).await,
         Some(cli::Cmd::User(usercmd)) => user(pool, usercmd).await,
+        Some(cli::Cmd::Group(groupcmd)) => group(pool, groupcmd).await,
+        Some(cli::Cmd::Crate(cratecmd)) => krate(pool, cratecmd).await,
+        Some(cli::Cmd::Audit(filter)) => audit(pool, filter).await,
     }
 }
 
 async fn serve(config: Configuration, pool: Pool) {
     let port = config.port();
     let state = AppState::new(config, pool);
-    let dlserver = ServeDir::new(state.config().crate_path());
+    tokio::spawn(index::invalidate_on_notify(state.clone()));
     let app = Router::new()
         .nest("/crates", index::router(&state))
+        .nest("/api/admin", admin::router(&state))
         .nest("/api", api::router(&state))
-        .nest_service("/download", dlserver)
+        .merge(download::router(&state))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
@@ -89,7 +106,7 @@ async fn serve(config: Configuration, pool: Pool) {
     let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
     info!("Starting server on {addr}...");
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("Failure when running axum");
 }
@@ -99,9 +116,37 @@ async fn user(pool: Pool, cmd: cli::User) {
     match cmd.command {
         cli::UserCmd::List => listusers(&mut conn).await,
         cli::UserCmd::Create { name, admin } => createuser(&mut conn, &name, admin).await,
+        cli::UserCmd::Delete { name, transfer_to } => {
+            deleteuser(&mut conn, &name, transfer_to.as_deref()).await
+        }
+        cli::UserCmd::Rename { name, new_name } => renameuser(&mut conn, &name, &new_name).await,
+        cli::UserCmd::SetAdmin { name } => setadmin(&mut conn, &name, true).await,
+        cli::UserCmd::UnsetAdmin { name } => setadmin(&mut conn, &name, false).await,
+        cli::UserCmd::Disable { name } => setdisabled(&mut conn, &name, true).await,
+        cli::UserCmd::Enable { name } => setdisabled(&mut conn, &name, false).await,
         cli::UserCmd::Tokens { name } => listtokens(&mut conn, &name).await,
-        cli::UserCmd::NewToken { name, title } => newtoken(&mut conn, &name, &title).await,
-        cli::UserCmd::DeleteToken { name, token } => deletetoken(&mut conn, &name, &token).await,
+        cli::UserCmd::NewToken {
+            name,
+            title,
+            scopes,
+            crates,
+            expires_in,
+        } => {
+            let scopes = TokenScopes {
+                endpoints: (!scopes.is_empty()).then_some(scopes),
+                crates: (!crates.is_empty()).then_some(crates),
+            };
+            let expires_at = expires_in.map(|expires_in| OffsetDateTime::now_utc() + expires_in);
+            newtoken(&mut conn, &name, &title, &scopes, expires_at).await
+        }
+        cli::UserCmd::DeleteToken { name, token } => deletetoken(&mut conn, &name, token).await,
+        cli::UserCmd::Keys { name } => listkeys(&mut conn, &name).await,
+        cli::UserCmd::AddKey {
+            name,
+            title,
+            paserk,
+        } => addkey(&mut conn, &name, &title, &paserk).await,
+        cli::UserCmd::DeleteKey { name, key_id } => deletekey(&mut conn, &name, &key_id).await,
     }
 }
 
@@ -112,13 +157,14 @@ async fn listusers(conn: &mut AsyncPgConnection) {
 
     for user in users {
         println!(
-            "{} is {}, and has {} tokens",
+            "{} is {}{}, and has {} tokens",
             user.name,
             if user.admin {
                 "an admin"
             } else {
                 "a normal user"
             },
+            if user.disabled { " (disabled)" } else { "" },
             user.tokens(conn)
                 .await
                 .expect("Unable to list user tokens")
@@ -128,12 +174,194 @@ async fn listusers(conn: &mut AsyncPgConnection) {
 }
 
 async fn createuser(conn: &mut AsyncPgConnection, name: &str, admin: bool) {
-    let user = database::models::Identity::new(conn, name, admin)
+    let user = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                let user = database::models::Identity::new(conn, name, admin).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: admin.then_some("admin"),
+                    ..NewAuditEvent::new(AuditAction::CreateUser)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(user)
+            })
+        })
         .await
         .expect("Unable to create user");
     println!("User {} created.", user.name);
 }
 
+/// Why `deleteuser` rolled back the deletion
+#[derive(Debug)]
+enum DeleteUserError {
+    /// Crates which would be left with nobody able to act for them
+    Orphans(Vec<String>),
+    Database(DieselError),
+}
+
+impl From<DieselError> for DeleteUserError {
+    fn from(e: DieselError) -> Self {
+        DeleteUserError::Database(e)
+    }
+}
+
+async fn deleteuser(conn: &mut AsyncPgConnection, name: &str, transfer_to: Option<&str>) {
+    let user = find_user(conn, name).await;
+    let heir = match transfer_to {
+        Some(heir) => Some(find_user(conn, heir).await),
+        None => None,
+    };
+    if heir.as_ref().is_some_and(|heir| heir.id == user.id) {
+        println!("Cannot transfer {}'s crates to themselves", user.name);
+        return;
+    }
+    let user = &user;
+    let result = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                let krates = user.krates(conn).await?;
+                let mut team_krates = user.team_krates(conn).await?;
+                team_krates
+                    .retain(|team_krate| krates.iter().all(|krate| krate.id != team_krate.id));
+                let mut orphans = Vec::new();
+                for krate in &krates {
+                    krate.lock_owners(conn).await?;
+                    if !krate.has_owner_except(conn, Some(user)).await? {
+                        orphans.push(krate.name.clone());
+                    }
+                }
+                // A team whose only member is this user could no longer act
+                // for its crates
+                let mut stranded = Vec::new();
+                for krate in &team_krates {
+                    krate.lock_owners(conn).await?;
+                    if !krate.has_owner_except(conn, Some(user)).await? {
+                        stranded.push(krate);
+                    }
+                }
+                match &heir {
+                    Some(heir) => {
+                        for krate in krates.iter().chain(stranded) {
+                            krate.add_owner(conn, heir).await?;
+                            NewAuditEvent {
+                                subject: Some(heir.id),
+                                krate: Some(&krate.name),
+                                ..NewAuditEvent::new(AuditAction::AddOwner)
+                            }
+                            .record(conn)
+                            .await?;
+                        }
+                    }
+                    None => {
+                        orphans.extend(stranded.into_iter().map(|krate| krate.name.clone()));
+                        if !orphans.is_empty() {
+                            orphans.sort();
+                            return Err(DeleteUserError::Orphans(orphans));
+                        }
+                    }
+                }
+                user.delete(conn).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&user.name),
+                    ..NewAuditEvent::new(AuditAction::DeleteUser)
+                }
+                .record(conn)
+                .await?;
+                Ok(())
+            })
+        })
+        .await;
+    match result {
+        Ok(()) => println!("User {} deleted.", user.name),
+        Err(DeleteUserError::Orphans(orphans)) => println!(
+            "{} is the only owner of {}, use --transfer-to to give them to another user",
+            user.name,
+            orphans.join(", ")
+        ),
+        Err(DeleteUserError::Database(e)) => panic!("Unable to delete user: {e:?}"),
+    }
+}
+
+async fn renameuser(conn: &mut AsyncPgConnection, name: &str, new_name: &str) {
+    let mut user = find_user(conn, name).await;
+    let detail = format!("{name} -> {new_name}");
+    let detail = &detail;
+    conn.transaction(|conn| {
+        Box::pin(async move {
+            user.rename(conn, new_name).await?;
+            NewAuditEvent {
+                subject: Some(user.id),
+                detail: Some(detail),
+                ..NewAuditEvent::new(AuditAction::RenameUser)
+            }
+            .record(conn)
+            .await
+        })
+    })
+    .await
+    .expect("Unable to rename user");
+    println!("User {name} renamed to {new_name}.");
+}
+
+async fn setadmin(conn: &mut AsyncPgConnection, name: &str, admin: bool) {
+    let mut user = find_user(conn, name).await;
+    let user = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                user.set_admin(conn, admin).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    ..NewAuditEvent::new(if admin {
+                        AuditAction::SetAdmin
+                    } else {
+                        AuditAction::UnsetAdmin
+                    })
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(user)
+            })
+        })
+        .await
+        .expect("Unable to update user");
+    if admin {
+        println!("{} is now an admin.", user.name);
+    } else {
+        println!("{} is no longer an admin.", user.name);
+    }
+}
+
+async fn setdisabled(conn: &mut AsyncPgConnection, name: &str, disabled: bool) {
+    let mut user = find_user(conn, name).await;
+    let user = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                user.set_disabled(conn, disabled).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    ..NewAuditEvent::new(if disabled {
+                        AuditAction::DisableUser
+                    } else {
+                        AuditAction::EnableUser
+                    })
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(user)
+            })
+        })
+        .await
+        .expect("Unable to update user");
+    if disabled {
+        println!("{} is now disabled.", user.name);
+    } else {
+        println!("{} is now enabled.", user.name);
+    }
+}
+
 async fn listtokens(conn: &mut AsyncPgConnection, name: &str) {
     let user = database::models::Identity::by_name(conn, name)
         .await
@@ -145,35 +373,727 @@ async fn listtokens(conn: &mut AsyncPgConnection, name: &str) {
         .expect("Unable to retrieve token list");
     println!("User {} has {} tokens.", user.name, tokens.len());
     for token in tokens {
-        println!("{} - {}", token.content, token.title);
+        println!(
+            "{}: {}... - {}{}",
+            token.id,
+            token.prefix,
+            token.title,
+            if token.is_expired() { " (expired)" } else { "" }
+        );
+        println!("    created: {}", format_time(token.created_at));
+        println!(
+            "    expires: {}",
+            token
+                .expires_at
+                .map(format_time)
+                .as_deref()
+                .unwrap_or("never")
+        );
+        println!(
+            "    last used: {}",
+            token
+                .last_used_at
+                .map(format_time)
+                .as_deref()
+                .unwrap_or("never")
+        );
+        if let Some(scopes) = token.endpoint_scopes() {
+            println!("    endpoints: {}", scopes.join(", "));
+        }
+        if let Some(crates) = token.crate_scopes() {
+            println!("    crates: {}", crates.join(", "));
+        }
     }
 }
 
-async fn newtoken(conn: &mut AsyncPgConnection, name: &str, title: &str) {
+async fn newtoken(
+    conn: &mut AsyncPgConnection,
+    name: &str,
+    title: &str,
+    scopes: &TokenScopes,
+    expires_at: Option<OffsetDateTime>,
+) {
     let user = database::models::Identity::by_name(conn, name)
         .await
         .expect("Unable to query for user")
         .expect("Unable to find user");
-    let token = user
-        .new_token(conn, title)
+    let user = &user;
+    let secret = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                let (token, secret) = user.new_token(conn, title, scopes, expires_at).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&token.prefix),
+                    ..NewAuditEvent::new(AuditAction::CreateToken)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(secret)
+            })
+        })
         .await
         .expect("Unable to create new token");
-    println!("{}", token.content);
+    println!("{secret}");
 }
 
-async fn deletetoken(conn: &mut AsyncPgConnection, name: &str, token: &str) {
+async fn deletetoken(conn: &mut AsyncPgConnection, name: &str, id: i32) {
     let user = database::models::Identity::by_name(conn, name)
         .await
         .expect("Unable to query for user")
         .expect("Unable to find user");
-    if user
-        .delete_token(conn, token)
+    let user = &user;
+    let deleted = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                let Some(token) = user.delete_token(conn, id).await? else {
+                    return Ok(false);
+                };
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&token.prefix),
+                    ..NewAuditEvent::new(AuditAction::DeleteToken)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(true)
+            })
+        })
         .await
-        .expect("Unable to delete token")
-        > 0
-    {
+        .expect("Unable to delete token");
+    if deleted {
         println!("Token removed");
     } else {
         println!("Token not found");
     }
 }
+
+async fn listkeys(conn: &mut AsyncPgConnection, name: &str) {
+    let user = database::models::Identity::by_name(conn, name)
+        .await
+        .expect("Unable to query for user")
+        .expect("Unable to find user");
+    let keys = user
+        .public_keys(conn)
+        .await
+        .expect("Unable to retrieve key list");
+    println!("User {} has {} public keys.", user.name, keys.len());
+    for key in keys {
+        println!("{} - {}", key.key_id, key.title);
+        println!("    added: {}", format_time(key.created_at));
+    }
+}
+
+async fn addkey(conn: &mut AsyncPgConnection, name: &str, title: &str, key: &paseto::PublicKey) {
+    let user = database::models::Identity::by_name(conn, name)
+        .await
+        .expect("Unable to query for user")
+        .expect("Unable to find user");
+    let user = &user;
+    let key = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                let key = user
+                    .add_public_key(conn, title, &key.key_id(), &key.paserk())
+                    .await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&key.key_id),
+                    ..NewAuditEvent::new(AuditAction::AddKey)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(key)
+            })
+        })
+        .await
+        .expect("Unable to add public key");
+    println!("Key {} added for {}", key.key_id, user.name);
+}
+
+async fn deletekey(conn: &mut AsyncPgConnection, name: &str, key_id: &str) {
+    let user = database::models::Identity::by_name(conn, name)
+        .await
+        .expect("Unable to query for user")
+        .expect("Unable to find user");
+    let user = &user;
+    let deleted = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                if user.delete_public_key(conn, key_id).await? == 0 {
+                    return Ok(false);
+                }
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(key_id),
+                    ..NewAuditEvent::new(AuditAction::DeleteKey)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(true)
+            })
+        })
+        .await
+        .expect("Unable to delete key");
+    if deleted {
+        println!("Key removed");
+    } else {
+        println!("Key not found");
+    }
+}
+
+async fn krate(pool: Pool, cmd: cli::Crate) {
+    let mut conn = pool.get().await.expect("Could not get DB connection");
+    match cmd.command {
+        cli::CrateCmd::List => listcrates(&mut conn).await,
+        cli::CrateCmd::Versions { name } => listversions(&mut conn, &name).await,
+        cli::CrateCmd::Yank { name, version } => setyanked(&mut conn, &name, &version, true).await,
+        cli::CrateCmd::Unyank { name, version } => {
+            setyanked(&mut conn, &name, &version, false).await
+        }
+        cli::CrateCmd::Transfer { name, owner } => transfercrate(&mut conn, &name, &owner).await,
+        cli::CrateCmd::Show { name, version } => showversion(&mut conn, &name, &version).await,
+        cli::CrateCmd::Pending => listpending(&mut conn).await,
+        cli::CrateCmd::Approve { name, version } => approve(&mut conn, &name, &version).await,
+        cli::CrateCmd::Approval { name, policy } => setapproval(&mut conn, &name, policy).await,
+        cli::CrateCmd::Embargo {
+            name,
+            version,
+            until,
+        } => setembargo(&mut conn, &name, &version, Some(until)).await,
+        cli::CrateCmd::LiftEmbargo { name, version } => {
+            setembargo(&mut conn, &name, &version, None).await
+        }
+    }
+}
+
+async fn find_crate(conn: &mut AsyncPgConnection, name: &str) -> Krate {
+    Krate::by_name(conn, name)
+        .await
+        .expect("Unable to query for crate")
+        .expect("Unable to find crate")
+}
+
+async fn find_version(conn: &mut AsyncPgConnection, krate: &Krate, version: &str) -> KrateVer {
+    krate
+        .version(conn, version)
+        .await
+        .expect("Unable to query for version")
+        .expect("Unable to find version")
+}
+
+async fn owner_logins(conn: &mut AsyncPgConnection, krate: &Krate) -> Vec<String> {
+    let mut logins: Vec<_> = krate
+        .owners(conn)
+        .await
+        .expect("Unable to list owners")
+        .into_iter()
+        .map(|owner| owner.name)
+        .collect();
+    logins.extend(
+        krate
+            .team_owners(conn)
+            .await
+            .expect("Unable to list team owners")
+            .iter()
+            .map(Team::login),
+    );
+    logins
+}
+
+async fn listcrates(conn: &mut AsyncPgConnection) {
+    let krates = Krate::all(conn)
+        .await
+        .expect("Unable to extract crate list from database");
+
+    for krate in krates {
+        let max_version = krate
+            .max_version(conn)
+            .await
+            .expect("Unable to find latest version");
+        println!(
+            "{} {}, owned by {}",
+            krate.name,
+            max_version
+                .map(|version| version.to_string())
+                .as_deref()
+                .unwrap_or("(no versions)"),
+            owner_logins(conn, &krate).await.join(", ")
+        );
+    }
+}
+
+async fn listversions(conn: &mut AsyncPgConnection, name: &str) {
+    let krate = find_crate(conn, name).await;
+    let versions = krate
+        .versions(conn)
+        .await
+        .expect("Unable to retrieve version list");
+    println!("Crate {} has {} versions.", krate.name, versions.len());
+    for version in versions {
+        let embargo = version
+            .visible_from
+            .filter(|when| *when > OffsetDateTime::now_utc())
+            .map(|when| format!(" (embargoed until {})", format_time(when)))
+            .unwrap_or_default();
+        println!(
+            "{}{}{}{embargo}",
+            version.ver,
+            if version.yanked { " (yanked)" } else { "" },
+            if version.exposed {
+                ""
+            } else {
+                " (pending approval)"
+            }
+        );
+    }
+}
+
+async fn setyanked(conn: &mut AsyncPgConnection, name: &str, version: &str, yanked: bool) {
+    let krate = find_crate(conn, name).await;
+    let mut version = find_version(conn, &krate, version).await;
+    let krate = &krate;
+    let version = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                version.set_yanked(conn, yanked).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    version: Some(&version.ver),
+                    ..NewAuditEvent::new(if yanked {
+                        AuditAction::Yank
+                    } else {
+                        AuditAction::Unyank
+                    })
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(version)
+            })
+        })
+        .await
+        .expect("Unable to update version");
+    println!(
+        "{} version {} {}.",
+        krate.name,
+        version.ver,
+        if yanked { "yanked" } else { "unyanked" }
+    );
+}
+
+async fn transfercrate(conn: &mut AsyncPgConnection, name: &str, owner: &str) {
+    let krate = find_crate(conn, name).await;
+    let (user, team) = match owner.strip_prefix(TEAM_PREFIX) {
+        Some(team) => (None, Some(find_team(conn, team).await)),
+        None => (Some(find_user(conn, owner).await), None),
+    };
+    let krate = &krate;
+    conn.transaction(|conn| {
+        Box::pin(async move {
+            for old in krate.owners(conn).await? {
+                krate.remove_owner(conn, &old).await?;
+                NewAuditEvent {
+                    subject: Some(old.id),
+                    krate: Some(&krate.name),
+                    ..NewAuditEvent::new(AuditAction::RemoveOwner)
+                }
+                .record(conn)
+                .await?;
+            }
+            for old in krate.team_owners(conn).await? {
+                krate.remove_team_owner(conn, &old).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    detail: Some(&old.login()),
+                    ..NewAuditEvent::new(AuditAction::RemoveOwner)
+                }
+                .record(conn)
+                .await?;
+            }
+            if let Some(user) = &user {
+                krate.add_owner(conn, user).await?;
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    krate: Some(&krate.name),
+                    ..NewAuditEvent::new(AuditAction::AddOwner)
+                }
+                .record(conn)
+                .await?;
+            }
+            if let Some(team) = &team {
+                krate.add_team_owner(conn, team).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    detail: Some(&team.login()),
+                    ..NewAuditEvent::new(AuditAction::AddOwner)
+                }
+                .record(conn)
+                .await?;
+            }
+            Ok::<_, DieselError>(())
+        })
+    })
+    .await
+    .expect("Unable to transfer crate");
+    println!("{} is now owned by {owner}.", krate.name);
+}
+
+async fn showversion(conn: &mut AsyncPgConnection, name: &str, version: &str) {
+    let krate = find_crate(conn, name).await;
+    let version = find_version(conn, &krate, version).await;
+    println!("{}", version.index_line());
+}
+
+async fn listpending(conn: &mut AsyncPgConnection) {
+    let pending = KrateVer::pending(conn)
+        .await
+        .expect("Unable to list pending versions");
+    if pending.is_empty() {
+        println!("No versions are pending approval.");
+    }
+    for (krate, version) in pending {
+        println!("{} {}", krate.name, version.ver);
+    }
+}
+
+async fn approve(conn: &mut AsyncPgConnection, name: &str, version: &str) {
+    let krate = find_crate(conn, name).await;
+    let mut version = find_version(conn, &krate, version).await;
+    if version.exposed {
+        println!(
+            "{} version {} is already approved.",
+            krate.name, version.ver
+        );
+        return;
+    }
+    let krate = &krate;
+    let version = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                version.expose(conn).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    version: Some(&version.ver),
+                    ..NewAuditEvent::new(AuditAction::Approve)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(version)
+            })
+        })
+        .await
+        .expect("Unable to approve version");
+    println!("{} version {} approved.", krate.name, version.ver);
+}
+
+async fn setembargo(
+    conn: &mut AsyncPgConnection,
+    name: &str,
+    version: &str,
+    until: Option<OffsetDateTime>,
+) {
+    let krate = find_crate(conn, name).await;
+    let mut version = find_version(conn, &krate, version).await;
+    if until.is_some() && version.is_visible() {
+        println!(
+            "{} version {} is already visible, so cannot be embargoed.",
+            krate.name, version.ver
+        );
+        return;
+    }
+    let krate = &krate;
+    let detail = until.map(format_time);
+    let detail = detail.as_deref();
+    let version = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                version.set_visible_from(conn, until).await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    version: Some(&version.ver),
+                    detail,
+                    ..NewAuditEvent::new(if until.is_some() {
+                        AuditAction::Embargo
+                    } else {
+                        AuditAction::LiftEmbargo
+                    })
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(version)
+            })
+        })
+        .await
+        .expect("Unable to update version");
+    match until {
+        Some(when) => println!(
+            "{} version {} is embargoed until {}.",
+            krate.name,
+            version.ver,
+            format_time(when)
+        ),
+        None => println!(
+            "{} version {} is no longer embargoed.",
+            krate.name, version.ver
+        ),
+    }
+}
+
+async fn setapproval(conn: &mut AsyncPgConnection, name: &str, policy: cli::ApprovalPolicy) {
+    let mut krate = find_crate(conn, name).await;
+    let detail = format!("{policy:?}").to_lowercase();
+    let detail = &detail;
+    let krate = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                krate
+                    .set_requires_approval(conn, policy.requires_approval())
+                    .await?;
+                NewAuditEvent {
+                    krate: Some(&krate.name),
+                    detail: Some(detail),
+                    ..NewAuditEvent::new(AuditAction::SetApprovalPolicy)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(krate)
+            })
+        })
+        .await
+        .expect("Unable to update crate");
+    println!(
+        "New versions of {} {}.",
+        krate.name,
+        match policy.requires_approval() {
+            Some(true) => "must be approved",
+            Some(false) => "are exposed immediately",
+            None => "follow the registry's approval policy",
+        }
+    );
+}
+
+async fn group(pool: Pool, cmd: cli::Group) {
+    let mut conn = pool.get().await.expect("Could not get DB connection");
+    match cmd.command {
+        cli::GroupCmd::List => listteams(&mut conn).await,
+        cli::GroupCmd::Create { name } => createteam(&mut conn, &name).await,
+        cli::GroupCmd::Delete { name } => deleteteam(&mut conn, &name).await,
+        cli::GroupCmd::Members { name } => listmembers(&mut conn, &name).await,
+        cli::GroupCmd::AddMember { name, user } => addmember(&mut conn, &name, &user).await,
+        cli::GroupCmd::RemoveMember { name, user } => removemember(&mut conn, &name, &user).await,
+    }
+}
+
+async fn find_team(conn: &mut AsyncPgConnection, name: &str) -> Team {
+    Team::by_name(conn, name)
+        .await
+        .expect("Unable to query for team")
+        .expect("Unable to find team")
+}
+
+async fn find_user(conn: &mut AsyncPgConnection, name: &str) -> Identity {
+    Identity::by_name(conn, name)
+        .await
+        .expect("Unable to query for user")
+        .expect("Unable to find user")
+}
+
+async fn listteams(conn: &mut AsyncPgConnection) {
+    let teams = Team::all(conn)
+        .await
+        .expect("Unable to extract team list from database");
+
+    for team in teams {
+        println!(
+            "{} has {} members, and owns {} crates",
+            team.login(),
+            team.members(conn)
+                .await
+                .expect("Unable to list team members")
+                .len(),
+            team.krates(conn)
+                .await
+                .expect("Unable to list team crates")
+                .len(),
+        );
+    }
+}
+
+async fn createteam(conn: &mut AsyncPgConnection, name: &str) {
+    let team = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                let team = Team::new(conn, name).await?;
+                NewAuditEvent {
+                    detail: Some(&team.login()),
+                    ..NewAuditEvent::new(AuditAction::CreateTeam)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(team)
+            })
+        })
+        .await
+        .expect("Unable to create team");
+    println!("Team {} created.", team.login());
+}
+
+async fn deleteteam(conn: &mut AsyncPgConnection, name: &str) {
+    let team = find_team(conn, name).await;
+    let krates = team.krates(conn).await.expect("Unable to list team crates");
+    if !krates.is_empty() {
+        let names: Vec<_> = krates.iter().map(|krate| krate.name.as_str()).collect();
+        println!(
+            "{} still owns {}, remove it as an owner first",
+            team.login(),
+            names.join(", ")
+        );
+        return;
+    }
+    let team = &team;
+    conn.transaction(|conn| {
+        Box::pin(async move {
+            team.delete(conn).await?;
+            NewAuditEvent {
+                detail: Some(&team.login()),
+                ..NewAuditEvent::new(AuditAction::DeleteTeam)
+            }
+            .record(conn)
+            .await
+        })
+    })
+    .await
+    .expect("Unable to delete team");
+    println!("Team {} deleted.", team.login());
+}
+
+async fn listmembers(conn: &mut AsyncPgConnection, name: &str) {
+    let team = find_team(conn, name).await;
+    let members = team
+        .members(conn)
+        .await
+        .expect("Unable to list team members");
+    println!("Team {} has {} members.", team.login(), members.len());
+    for member in members {
+        println!("{}", member.name);
+    }
+}
+
+async fn addmember(conn: &mut AsyncPgConnection, name: &str, user: &str) {
+    let team = find_team(conn, name).await;
+    let user = find_user(conn, user).await;
+    let team = &team;
+    let user = &user;
+    let changed = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                if team.add_member(conn, user).await? == 0 {
+                    return Ok(false);
+                }
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&team.login()),
+                    ..NewAuditEvent::new(AuditAction::AddMember)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(true)
+            })
+        })
+        .await
+        .expect("Unable to add team member");
+    if changed {
+        println!("{} added to {}", user.name, team.login());
+    } else {
+        println!("{} is already a member of {}", user.name, team.login());
+    }
+}
+
+async fn removemember(conn: &mut AsyncPgConnection, name: &str, user: &str) {
+    let team = find_team(conn, name).await;
+    let user = find_user(conn, user).await;
+    let team = &team;
+    let user = &user;
+    let changed = conn
+        .transaction(|conn| {
+            Box::pin(async move {
+                if team.remove_member(conn, user).await? == 0 {
+                    return Ok(false);
+                }
+                NewAuditEvent {
+                    subject: Some(user.id),
+                    detail: Some(&team.login()),
+                    ..NewAuditEvent::new(AuditAction::RemoveMember)
+                }
+                .record(conn)
+                .await?;
+                Ok::<_, DieselError>(true)
+            })
+        })
+        .await
+        .expect("Unable to remove team member");
+    if changed {
+        println!("{} removed from {}", user.name, team.login());
+    } else {
+        println!("{} is not a member of {}", user.name, team.login());
+    }
+}
+
+async fn audit(pool: Pool, args: cli::Audit) {
+    let mut conn = pool.get().await.expect("Could not get DB connection");
+    let identity = match &args.user {
+        Some(name) => Some(
+            database::models::Identity::by_name(&mut conn, name)
+                .await
+                .expect("Unable to query for user")
+                .expect("Unable to find user")
+                .id,
+        ),
+        None => None,
+    };
+    let filter = AuditFilter {
+        identity,
+        krate: args.krate,
+        action: args.action,
+        since: args.since,
+        until: args.until,
+        limit: args.limit,
+    };
+    let events = AuditEvent::search(&mut conn, &filter)
+        .await
+        .expect("Unable to retrieve audit events");
+    let names: HashMap<i32, String> = database::models::Identity::all(&mut conn)
+        .await
+        .expect("Unable to extract user list from database")
+        .into_iter()
+        .map(|user| (user.id, user.name))
+        .collect();
+    let name_of = |id: i32| names.get(&id).cloned().unwrap_or_else(|| format!("#{id}"));
+
+    for event in events {
+        let mut line = format!("{} {}", format_time(event.created_at), event.action);
+        if let Some(krate) = &event.krate {
+            line.push_str(&format!(" {krate}"));
+        }
+        if let Some(version) = &event.version {
+            line.push_str(&format!(" {version}"));
+        }
+        if let Some(subject) = event.subject {
+            line.push_str(&format!(" for {}", name_of(subject)));
+        }
+        if let Some(detail) = &event.detail {
+            line.push_str(&format!(" [{detail}]"));
+        }
+        match event.actor {
+            Some(actor) => line.push_str(&format!(" by {}", name_of(actor))),
+            None => line.push_str(" by the command line"),
+        }
+        if let Some(token) = event.token {
+            line.push_str(&format!(" using token #{token}"));
+        }
+        if let Some(source) = &event.source {
+            line.push_str(&format!(" from {source}"));
+        }
+        println!("{line}");
+    }
+}
@@ -0,0 +1,351 @@
+//! Asymmetric tokens, as produced by cargo's `cargo:paseto` credential provider
+//!
+//! These are PASETO v3.public tokens, signed with a P-384 key which only the
+//! user holds.  We know the matching public key in its PASERK form, and find
+//! it using the key ID which cargo places in the token footer.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use p384::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha384};
+use thiserror::Error;
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+const TOKEN_HEADER: &str = "v3.public.";
+const PASERK_PUBLIC_HEADER: &str = "k3.public.";
+const PASERK_ID_HEADER: &str = "k3.pid.";
+/// Length of a P-384 signature, r and s concatenated
+const SIGNATURE_LEN: usize = 96;
+/// Length of a PASERK ID hash
+const KEY_ID_LEN: usize = 33;
+
+#[derive(Debug, Error)]
+pub enum PasetoError {
+    #[error("Token is not a v3.public PASETO")]
+    NotPaseto,
+    #[error("Malformed token: {0}")]
+    Malformed(&'static str),
+    #[error("Bad base64 in token: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Bad token footer: {0}")]
+    Footer(serde_json::Error),
+    #[error("Bad token claims: {0}")]
+    Claims(serde_json::Error),
+    #[error("Bad issue time in token: {0}")]
+    IssuedAt(#[from] time::error::Parse),
+    #[error("Token issue time {0} is too far from the current time")]
+    OutOfWindow(String),
+    #[error("Key is not a k3.public PASERK")]
+    NotPaserk,
+    #[error("Invalid public key")]
+    BadKey,
+    #[error("Token signature does not verify")]
+    BadSignature,
+}
+
+/// A P-384 public key
+#[derive(Clone, Debug)]
+pub struct PublicKey(VerifyingKey);
+
+impl PublicKey {
+    /// Parse a public key in PASERK form, `k3.public.<base64>`
+    pub fn from_paserk(paserk: &str) -> Result<Self, PasetoError> {
+        let encoded = paserk
+            .strip_prefix(PASERK_PUBLIC_HEADER)
+            .ok_or(PasetoError::NotPaserk)?;
+        let bytes = URL_SAFE_NO_PAD.decode(encoded)?;
+        VerifyingKey::from_sec1_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| PasetoError::BadKey)
+    }
+
+    /// The compressed point form of the key, as used by PASETO
+    fn compressed(&self) -> Vec<u8> {
+        self.0.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    /// The key in PASERK form
+    pub fn paserk(&self) -> String {
+        format!(
+            "{PASERK_PUBLIC_HEADER}{}",
+            URL_SAFE_NO_PAD.encode(self.compressed())
+        )
+    }
+
+    /// The PASERK ID of the key, `k3.pid.<base64>`, which cargo uses as the key ID
+    pub fn key_id(&self) -> String {
+        let mut hasher = Sha384::new();
+        hasher.update(PASERK_ID_HEADER);
+        hasher.update(self.paserk());
+        let hash = hasher.finalize();
+        format!(
+            "{PASERK_ID_HEADER}{}",
+            URL_SAFE_NO_PAD.encode(&hash[..KEY_ID_LEN])
+        )
+    }
+}
+
+/// The footer cargo attaches to its tokens
+#[derive(Debug, Deserialize)]
+pub struct Footer {
+    /// The index URL of the registry the token is for
+    pub url: String,
+    /// The PASERK ID of the signing key
+    pub kip: String,
+}
+
+/// The claims cargo makes in its tokens, see RFC 3231
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub iat: String,
+    pub mutation: Option<String>,
+    pub name: Option<String>,
+    pub vers: Option<String>,
+    pub cksum: Option<String>,
+    /// A value the registry handed out to be used once
+    pub challenge: Option<String>,
+    /// Version of the claims format, only 1 is defined
+    pub v: Option<u8>,
+}
+
+impl Claims {
+    pub fn issued_at(&self) -> Result<OffsetDateTime, PasetoError> {
+        Ok(OffsetDateTime::parse(&self.iat, &Rfc3339)?)
+    }
+
+    /// The issue time of the token, if it is within the window either side
+    /// of the current time
+    pub fn issued_within(&self, window: Duration) -> Result<OffsetDateTime, PasetoError> {
+        let issued_at = self.issued_at()?;
+        if (OffsetDateTime::now_utc() - issued_at).abs() > window {
+            return Err(PasetoError::OutOfWindow(self.iat.clone()));
+        }
+        Ok(issued_at)
+    }
+}
+
+/// A token which has been parsed, but whose signature is not yet checked
+pub struct UntrustedToken {
+    message: Vec<u8>,
+    signature: Signature,
+    raw_footer: Vec<u8>,
+    footer: Footer,
+}
+
+impl UntrustedToken {
+    pub fn parse(token: &str) -> Result<Self, PasetoError> {
+        let rest = token
+            .strip_prefix(TOKEN_HEADER)
+            .ok_or(PasetoError::NotPaseto)?;
+        let (payload, footer) = rest
+            .split_once('.')
+            .ok_or(PasetoError::Malformed("cargo tokens must have a footer"))?;
+        let mut payload = URL_SAFE_NO_PAD.decode(payload)?;
+        if payload.len() < SIGNATURE_LEN {
+            return Err(PasetoError::Malformed("payload too short"));
+        }
+        let signature = payload.split_off(payload.len() - SIGNATURE_LEN);
+        let signature = Signature::from_slice(&signature)
+            .map_err(|_| PasetoError::Malformed("bad signature"))?;
+        let raw_footer = URL_SAFE_NO_PAD.decode(footer)?;
+        let footer = serde_json::from_slice(&raw_footer).map_err(PasetoError::Footer)?;
+        Ok(Self {
+            message: payload,
+            signature,
+            raw_footer,
+            footer,
+        })
+    }
+
+    /// The footer of the token, which is not trustworthy until verified
+    pub fn footer(&self) -> &Footer {
+        &self.footer
+    }
+
+    /// A value identifying what the token says, for use as its nonce
+    ///
+    /// The signature is left out, since an ECDSA signature can be altered
+    /// without invalidating it, and so says nothing about the token's identity.
+    pub fn nonce(&self) -> String {
+        let hash = Sha384::digest(pre_auth_encode(&[&self.message, &self.raw_footer]));
+        URL_SAFE_NO_PAD.encode(hash)
+    }
+
+    /// Verify the token was signed by the given key, and return its claims
+    pub fn verify(self, key: &PublicKey) -> Result<(Footer, Claims), PasetoError> {
+        verify_signature(key, &self.message, &self.raw_footer, &self.signature)?;
+        let claims = serde_json::from_slice(&self.message).map_err(PasetoError::Claims)?;
+        Ok((self.footer, claims))
+    }
+}
+
+fn verify_signature(
+    key: &PublicKey,
+    message: &[u8],
+    footer: &[u8],
+    signature: &Signature,
+) -> Result<(), PasetoError> {
+    // Cargo provides no implicit assertion
+    let signed = pre_auth_encode(&[
+        &key.compressed(),
+        TOKEN_HEADER.as_bytes(),
+        message,
+        footer,
+        b"",
+    ]);
+    key.0
+        .verify(&signed, signature)
+        .map_err(|_| PasetoError::BadSignature)
+}
+
+/// PASETO's pre-authentication encoding of a list of pieces
+fn pre_auth_encode(pieces: &[&[u8]]) -> Vec<u8> {
+    let le64 = |n: usize| ((n as u64) & (u64::MAX >> 1)).to_le_bytes();
+    let mut out = Vec::new();
+    out.extend_from_slice(&le64(pieces.len()));
+    for piece in pieces {
+        out.extend_from_slice(&le64(piece.len()));
+        out.extend_from_slice(piece);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Public key of the official PASETO v3.public test vectors
+    const VECTOR_KEY: &str =
+        "k3.public.AvvLfGnuHGBXm-ejNBNIeNnFxb811VLatjwBQDl-0UzvY313IJJcRGmeow5yh0xy-w";
+
+    /// A key of our own, and a token cargo could have signed with it
+    const CARGO_KEY: &str =
+        "k3.public.A-Nd5FG_PFvcXcaUHcA9MB_ZSm1pyAr5v-yfnVVwKWuLKAQcck3r-oeA4Zu0nRC1AQ";
+    const CARGO_KEY_ID: &str = "k3.pid.nbD83w-RJjosAlRJQ2GQ0zlBwJ0p95n4Gf3cpMrWgwqs";
+    const CARGO_TOKEN: &str = "v3.public.eyJpYXQiOiIyMDIzLTEyLTI4VDA5OjAwOjAwWiIsIm11dGF0aW9uIjoieWFuayIsIm5hbWUiOiJmb28iLCJ2ZXJzIjoiMS4wLjAifbRV--mzuGuCzct4izX0slRhelCGGo5_iN_3nq1rhGDHFDZ_3HZtSvOU62JamZ5vVQhiDxhkFvl9uQproBqxRpX98Z1A7KhyNIqhJxrFh4LVZUF4SyKyYpBByWleTuPILA.eyJ1cmwiOiJzcGFyc2UraHR0cDovL2xvY2FsaG9zdDoxNTM3L2NyYXRlcy8iLCJraXAiOiJrMy5waWQubmJEODN3LVJKam9zQWxSSlEyR1EwemxCd0owcDk1bjRHZjNjcE1yV2d3cXMifQ";
+
+    /// Check a token's signature without insisting on cargo's footer
+    fn verify_vector(key: &str, token: &str) -> Result<Vec<u8>, PasetoError> {
+        let key = PublicKey::from_paserk(key)?;
+        let rest = token.strip_prefix(TOKEN_HEADER).unwrap();
+        let (payload, footer) = rest.split_once('.').unwrap_or((rest, ""));
+        let mut message = URL_SAFE_NO_PAD.decode(payload)?;
+        let signature = message.split_off(message.len() - SIGNATURE_LEN);
+        let signature = Signature::from_slice(&signature).unwrap();
+        verify_signature(&key, &message, &URL_SAFE_NO_PAD.decode(footer)?, &signature)?;
+        Ok(message)
+    }
+
+    /// Replace the footer of a token
+    fn with_footer(token: &str, footer: &str) -> String {
+        let (signed, _) = token.rsplit_once('.').unwrap();
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(footer))
+    }
+
+    fn claims(iat: &str) -> Claims {
+        serde_json::from_value(serde_json::json!({ "iat": iat })).unwrap()
+    }
+
+    #[test]
+    fn official_vectors() {
+        const MESSAGE: &[u8] =
+            br#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+        // 3-S-1
+        let message = verify_vector(VECTOR_KEY, "v3.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9qqEwwrKHKi5lJ7b9MBKc0G4MGZy0ptUiMv3lAUAaz-JY_zjoqBSIxMxhfAoeNYiSyvfUErj76KOPWm1OeNnBPkTSespeSXDGaDfxeIrl3bRrPEIy7tLwLAIsRzsXkfph").unwrap();
+        assert_eq!(message, MESSAGE);
+        // 3-S-2
+        let message = verify_vector(VECTOR_KEY, "v3.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9ZWrbGZ6L0MDK72skosUaS0Dz7wJ_2bMcM6tOxFuCasO9GhwHrvvchqgXQNLQQyWzGC2wkr-VKII71AvkLpC8tJOrzJV1cap9NRwoFzbcXjzMZyxQ0wkshxZxx8ImmNWP.eyJraWQiOiJkWWtJU3lseFFlZWNFY0hFTGZ6Rjg4VVpyd2JMb2xOaUNkcHpVSEd3OVVxbiJ9").unwrap();
+        assert_eq!(message, MESSAGE);
+    }
+
+    #[test]
+    fn official_key_id() {
+        // k3.pid-1
+        let paserk = "k3.public.AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        let key = PublicKey::from_paserk(paserk).unwrap();
+        assert_eq!(key.paserk(), paserk);
+        assert_eq!(
+            key.key_id(),
+            "k3.pid.mL4lGxNG7cz128frmpn83_76V9C7LmV2sHAMtJ8vIdwG"
+        );
+    }
+
+    #[test]
+    fn cargo_token() {
+        let key = PublicKey::from_paserk(CARGO_KEY).unwrap();
+        assert_eq!(key.key_id(), CARGO_KEY_ID);
+        let token = UntrustedToken::parse(CARGO_TOKEN).unwrap();
+        assert_eq!(token.footer().kip, CARGO_KEY_ID);
+        let (footer, claims) = token.verify(&key).unwrap();
+        assert_eq!(footer.url, "sparse+http://localhost:1537/crates/");
+        assert_eq!(claims.mutation.as_deref(), Some("yank"));
+        assert_eq!(claims.name.as_deref(), Some("foo"));
+        assert_eq!(claims.vers.as_deref(), Some("1.0.0"));
+    }
+
+    #[test]
+    fn bad_footer() {
+        let key = PublicKey::from_paserk(CARGO_KEY).unwrap();
+        let token = with_footer(CARGO_TOKEN, "not json");
+        assert!(matches!(
+            UntrustedToken::parse(&token),
+            Err(PasetoError::Footer(_))
+        ));
+        let token = CARGO_TOKEN.rsplit_once('.').unwrap().0;
+        assert!(matches!(
+            UntrustedToken::parse(token),
+            Err(PasetoError::Malformed(_))
+        ));
+        // The footer is signed, so cannot be pointed at another registry
+        let token = with_footer(
+            CARGO_TOKEN,
+            &format!(r#"{{"url":"sparse+https://example.com/","kip":"{CARGO_KEY_ID}"}}"#),
+        );
+        let token = UntrustedToken::parse(&token).unwrap();
+        assert!(matches!(token.verify(&key), Err(PasetoError::BadSignature)));
+    }
+
+    #[test]
+    fn bad_signature() {
+        let key = PublicKey::from_paserk(CARGO_KEY).unwrap();
+        let (payload, footer) = CARGO_TOKEN
+            .strip_prefix(TOKEN_HEADER)
+            .unwrap()
+            .split_once('.')
+            .unwrap();
+        let mut payload = URL_SAFE_NO_PAD.decode(payload).unwrap();
+        *payload.last_mut().unwrap() ^= 1;
+        let token = format!("{TOKEN_HEADER}{}.{footer}", URL_SAFE_NO_PAD.encode(payload));
+        let token = UntrustedToken::parse(&token).unwrap();
+        assert!(matches!(token.verify(&key), Err(PasetoError::BadSignature)));
+        // Nor will the token verify with anyone else's key
+        let other = PublicKey::from_paserk(VECTOR_KEY).unwrap();
+        let token = UntrustedToken::parse(CARGO_TOKEN).unwrap();
+        assert!(matches!(
+            token.verify(&other),
+            Err(PasetoError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn issue_time_window() {
+        let window = Duration::minutes(15);
+        let now = OffsetDateTime::now_utc();
+        let fresh = claims(&now.format(&Rfc3339).unwrap());
+        assert!(fresh.issued_within(window).is_ok());
+        let stale = claims(&(now - Duration::hours(1)).format(&Rfc3339).unwrap());
+        assert!(matches!(
+            stale.issued_within(window),
+            Err(PasetoError::OutOfWindow(_))
+        ));
+        let early = claims(&(now + Duration::hours(1)).format(&Rfc3339).unwrap());
+        assert!(matches!(
+            early.issued_within(window),
+            Err(PasetoError::OutOfWindow(_))
+        ));
+        assert!(matches!(
+            claims("yesterday").issued_within(window),
+            Err(PasetoError::IssuedAt(_))
+        ));
+    }
+}
@@ -1,19 +1,146 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
 use axum::extract::FromRef;
+use time::{Duration, OffsetDateTime};
 
-use crate::configuration::Configuration;
+use crate::{configuration::Configuration, index::IndexFile};
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     config: Configuration,
     pool: database::Pool,
+    index_cache: IndexCache,
 }
 
 impl AppState {
     pub fn new(config: Configuration, pool: database::Pool) -> Self {
-        Self { config, pool }
+        let index_cache = IndexCache::new(config.index_cache_size(), config.index_cache_ttl());
+        Self {
+            config,
+            pool,
+            index_cache,
+        }
     }
 
     pub fn config(&self) -> &Configuration {
         &self.config
     }
+
+    pub fn index_cache(&self) -> &IndexCache {
+        &self.index_cache
+    }
+}
+
+/// Rendered index files, keyed by lowercased crate name
+///
+/// Entries are dropped when the database announces a change to the crate,
+/// and in any case once they are older than the configured TTL.
+#[derive(Clone)]
+pub struct IndexCache {
+    inner: Arc<Mutex<IndexCacheInner>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+#[derive(Default)]
+struct IndexCacheInner {
+    entries: HashMap<String, CachedIndex>,
+    /// Bumped by every invalidation, so that a file rendered from the
+    /// database before a change is not cached after it
+    generation: u64,
+}
+
+struct CachedIndex {
+    file: Arc<IndexFile>,
+    inserted: OffsetDateTime,
+    expires: OffsetDateTime,
+}
+
+impl IndexCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity,
+            ttl,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, IndexCacheInner> {
+        self.inner.lock().expect("Index cache poisoned")
+    }
+
+    /// The current generation, to be passed to [`IndexCache::insert`] once
+    /// the file has been rendered
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<IndexFile>> {
+        let mut inner = self.lock();
+        let cached = inner.entries.get(name)?;
+        if cached.expires > OffsetDateTime::now_utc() {
+            Some(Arc::clone(&cached.file))
+        } else {
+            inner.entries.remove(name);
+            None
+        }
+    }
+
+    /// Cache a rendered file, unless anything has been invalidated since the
+    /// given generation.  The file must not outlive `until`, when an embargo
+    /// will change it.
+    pub fn insert(
+        &self,
+        name: String,
+        file: Arc<IndexFile>,
+        generation: u64,
+        until: Option<OffsetDateTime>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.generation != generation {
+            return;
+        }
+        let now = OffsetDateTime::now_utc();
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&name) {
+            inner.entries.retain(|_, cached| cached.expires > now);
+        }
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&name) {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.inserted)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        let expires = until.map_or(now + self.ttl, |until| until.min(now + self.ttl));
+        inner.entries.insert(
+            name,
+            CachedIndex {
+                file,
+                inserted: now,
+                expires,
+            },
+        );
+    }
+
+    pub fn invalidate(&self, name: &str) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.entries.remove(name);
+    }
+
+    /// Drop everything, for when changes may have gone unannounced
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.entries.clear();
+    }
 }